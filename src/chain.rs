@@ -1,4 +1,86 @@
 use std::any::Any;
+use std::cell::RefCell;
+use parser::ParsingDataTypes;
+use value::{self, Field, FromParsed};
+
+/// How a store writes into its field
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoreMode {
+    /// Overwrites the field
+    Replace,
+    /// Appends to the end of a `String` field
+    Append,
+    /// Pushes into a `Vec<T>` field
+    Push,
+}
+
+/// Byte order of a binary number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Binary numbers a chain can decode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binary {
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl Binary {
+    /// Width in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Binary::U16 | Binary::I16 => 2,
+            Binary::U32 | Binary::I32 | Binary::F32 => 4,
+            Binary::U64 | Binary::I64 | Binary::F64 => 8,
+        }
+    }
+}
+
+type Getter<'c> = Box<dyn Fn(&mut dyn Any) -> Option<&mut dyn Field> + 'c>;
+
+/// Finds a field in the output handed to `Parser::parse_into`, so the
+/// parser doesn't need to borrow it when it is built
+pub struct Accessor<'c> {
+    get: Getter<'c>,
+    accepts: fn(StoreMode, ParsingDataTypes) -> bool,
+}
+
+impl<'c> Accessor<'c> {
+    /// `Accessor::new(|atom: &mut ATOM| &mut atom.serial)`
+    pub fn new<T, F, A>(accessor: A) -> Accessor<'c>
+        where T: Any, F: FromParsed + Any, A: Fn(&mut T) -> &mut F + 'c
+    {
+        Accessor {
+            get: Box::new(move |output: &mut dyn Any| -> Option<&mut dyn Field> {
+                match output.downcast_mut::<T>() {
+                    Some(target) => Some(accessor(target)),
+                    None => None,
+                }
+            }),
+            accepts: value::accepts::<F>,
+        }
+    }
+
+    /// If `mode` can write a value of `dtype` into the field
+    pub fn accepts(&self, mode: StoreMode, dtype: ParsingDataTypes) -> bool {
+        (self.accepts)(mode, dtype)
+    }
+
+    /// The field inside `output`, None when `output` isn't what the
+    /// accessor was made for
+    pub fn get<'o>(&self, output: &'o mut dyn Any) -> Option<&'o mut dyn Field> {
+        (self.get)(output)
+    }
+}
 
 pub enum Operation<'c> {
     Trim,
@@ -11,8 +93,16 @@ pub enum Operation<'c> {
     ToF64,
     ToU8,
     ToByte,
+    /// Raw bytes of the value, for storing into a `Vec<u8>` or decoding
+    Bytes,
+    /// Decodes exactly `Binary::size` bytes as a number
+    Decode(Endian, Binary),
     Or(Vec<Chain<'c>>),
-    Store(Box<&'c mut Any>),
+    Store(StoreMode, RefCell<&'c mut dyn Field>),
+    /// Store into a field of the output given at parse time
+    StoreAt(StoreMode, Accessor<'c>),
+    /// Puts the value in the record of the parse, under this name
+    Capture(String),
     Opt(Chain<'c>),
 }
 
@@ -21,7 +111,7 @@ pub struct Chain<'c> {
 }
 
 impl<'c> Chain<'c> {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Chain { olist: vec![] }
     }
 
@@ -90,10 +180,63 @@ impl<'c> Chain<'c> {
         self
     }
 
+    pub fn bytes(mut self) -> Self {
+        self.olist.push(Operation::Bytes);
+        self
+    }
+
+    pub fn decode(mut self, endian: Endian, binary: Binary) -> Self {
+        self.olist.push(Operation::Decode(endian, binary));
+        self
+    }
+
     pub fn store<T>(mut self, field: &'c mut T) -> Self
-        where T: Any
+        where T: FromParsed + Any
+    {
+        self.olist.push(Operation::Store(StoreMode::Replace, RefCell::new(field)));
+        self
+    }
+
+    #[allow(clippy::ptr_arg)]
+    pub fn append(mut self, field: &'c mut String) -> Self {
+        self.olist.push(Operation::Store(StoreMode::Append, RefCell::new(field)));
+        self
+    }
+
+    #[allow(clippy::ptr_arg)]
+    pub fn push<T>(mut self, field: &'c mut Vec<T>) -> Self
+        where T: FromParsed + Any
+    {
+        self.olist.push(Operation::Store(StoreMode::Push, RefCell::new(field)));
+        self
+    }
+
+    pub fn store_at<T, F, A>(mut self, accessor: A) -> Self
+        where T: Any, F: FromParsed + Any, A: Fn(&mut T) -> &mut F + 'c
+    {
+        self.olist.push(Operation::StoreAt(StoreMode::Replace, Accessor::new(accessor)));
+        self
+    }
+
+    pub fn append_at<T, A>(mut self, accessor: A) -> Self
+        where T: Any, A: Fn(&mut T) -> &mut String + 'c
     {
-        self.olist.push(Operation::Store(Box::new(field)));
+        self.olist.push(Operation::StoreAt(StoreMode::Append, Accessor::new(accessor)));
+        self
+    }
+
+    pub fn push_at<T, F, A>(mut self, accessor: A) -> Self
+        where T: Any, F: FromParsed + Any, A: Fn(&mut T) -> &mut Vec<F> + 'c
+    {
+        self.olist.push(Operation::StoreAt(StoreMode::Push, Accessor::new(accessor)));
+        self
+    }
+
+    /// Puts the value in the record of `parse_record` under `name`. Only
+    /// named parsers open a nested record, the captures of an unnamed
+    /// sequence land in the record around it.
+    pub fn capture(mut self, name: &str) -> Self {
+        self.olist.push(Operation::Capture(name.to_string()));
         self
     }
 
@@ -127,6 +270,52 @@ pub fn to_f64<'c>() -> Chain<'c> {
     c
 }
 
+pub fn bytes<'c>() -> Chain<'c> {
+    let mut c = Chain::new();
+    c.olist.push(Operation::Bytes);
+    c
+}
+
+pub fn decode<'c>(endian: Endian, binary: Binary) -> Chain<'c> {
+    let mut c = Chain::new();
+    c.olist.push(Operation::Decode(endian, binary));
+    c
+}
+
+// Shorthands for decode, `le_u16()` is `decode(Endian::Little, Binary::U16)`
+macro_rules! decoders {
+    ($($name:ident => $endian:ident, $binary:ident;)*) => {
+        impl<'c> Chain<'c> {
+            $(pub fn $name(self) -> Self {
+                self.decode(Endian::$endian, Binary::$binary)
+            })*
+        }
+
+        $(pub fn $name<'c>() -> Chain<'c> {
+            decode(Endian::$endian, Binary::$binary)
+        })*
+    };
+}
+
+decoders! {
+    le_u16 => Little, U16;
+    be_u16 => Big, U16;
+    le_i16 => Little, I16;
+    be_i16 => Big, I16;
+    le_u32 => Little, U32;
+    be_u32 => Big, U32;
+    le_i32 => Little, I32;
+    be_i32 => Big, I32;
+    le_u64 => Little, U64;
+    be_u64 => Big, U64;
+    le_i64 => Little, I64;
+    be_i64 => Big, I64;
+    le_f32 => Little, F32;
+    be_f32 => Big, F32;
+    le_f64 => Little, F64;
+    be_f64 => Big, F64;
+}
+
 pub fn empty<'c>() -> Chain<'c> {
     let mut c = Chain::new();
     c.olist.push(Operation::Empty);
@@ -187,10 +376,48 @@ pub fn opt<'c>(chain : Chain<'c>) -> Chain<'c> {
     c
 }
 
-pub fn store<'c, 'o, T>(field: &'c mut T) -> Chain<'c>
-    where T: Any
+pub fn store<'c, T>(field: &'c mut T) -> Chain<'c>
+    where T: FromParsed + Any
+{
+    let mut c = Chain::new();
+    c.olist.push(Operation::Store(StoreMode::Replace, RefCell::new(field)));
+    c
+}
+
+#[allow(clippy::ptr_arg)]
+pub fn append(field: &mut String) -> Chain<'_> {
+    let mut c = Chain::new();
+    c.olist.push(Operation::Store(StoreMode::Append, RefCell::new(field)));
+    c
+}
+
+#[allow(clippy::ptr_arg)]
+pub fn push<'c, T>(field: &'c mut Vec<T>) -> Chain<'c>
+    where T: FromParsed + Any
 {
     let mut c = Chain::new();
-    c.olist.push(Operation::Store(Box::new(field)));
+    c.olist.push(Operation::Store(StoreMode::Push, RefCell::new(field)));
     c
-}
\ No newline at end of file
+}
+
+pub fn store_at<'c, T, F, A>(accessor: A) -> Chain<'c>
+    where T: Any, F: FromParsed + Any, A: Fn(&mut T) -> &mut F + 'c
+{
+    Chain::new().store_at(accessor)
+}
+
+pub fn append_at<'c, T, A>(accessor: A) -> Chain<'c>
+    where T: Any, A: Fn(&mut T) -> &mut String + 'c
+{
+    Chain::new().append_at(accessor)
+}
+
+pub fn push_at<'c, T, F, A>(accessor: A) -> Chain<'c>
+    where T: Any, F: FromParsed + Any, A: Fn(&mut T) -> &mut Vec<F> + 'c
+{
+    Chain::new().push_at(accessor)
+}
+
+pub fn capture<'c>(name: &str) -> Chain<'c> {
+    Chain::new().capture(name)
+}
@@ -0,0 +1,222 @@
+use std::error;
+use std::fmt;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use input::Input;
+use parser::{Parser, ParseError};
+use record::Record;
+use value::Value;
+
+/// Fills a `#[derive(Deserialize)]` type from the captures of a parse,
+/// fields by their capture names:
+///
+/// ```text
+/// take 5 -> trim -> to_i32 -> capture serial
+/// ```
+///
+/// goes into `serial: i32`. Named parsers become nested structs, and
+/// repetitions `Vec`s. A field without a capture is missing, which is fine
+/// for an `Option` or a `#[serde(default)]`.
+pub fn from_record<T: DeserializeOwned>(record: Record) -> Result<T, DeError> {
+    T::deserialize(RecordDeserializer(record))
+}
+
+/// Parses `buffer` and deserializes its captures
+pub fn parse<'d, T: DeserializeOwned, I: Input<'d>>(parser: &Parser, buffer: I) -> Result<(T, Option<I>), DeError> {
+    match parser.parse_record(buffer) {
+        Ok((record, rest)) => Ok((from_record(record)?, rest)),
+        Err(e) => Err(DeError::Parse(e)),
+    }
+}
+
+#[derive(Debug)]
+pub enum DeError {
+    // The parse itself failed
+    Parse(ParseError),
+    // The captures don't fit the type, from serde
+    Message(String),
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeError::Parse(ref e) => write!(f, "{}", e),
+            DeError::Message(ref m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> DeError {
+        DeError::Message(msg.to_string())
+    }
+}
+
+/// A record seen as a map, from `from_record`
+pub struct RecordDeserializer(pub Record);
+
+impl<'de> de::Deserializer<'de> for RecordDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let fields = self.0.into_iter().map(|(name, value)| (name, ValueDeserializer(value)));
+        let mut map = MapDeserializer::new(fields);
+        let result = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(result)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// One captured value
+pub struct ValueDeserializer(pub Value);
+
+impl<'de> IntoDeserializer<'de, DeError> for ValueDeserializer {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> ValueDeserializer {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            Value::Str(s) => visitor.visit_string(s),
+            Value::Bytes(b) => visitor.visit_byte_buf(b),
+            Value::I32(i) => visitor.visit_i32(i),
+            Value::F64(f) => visitor.visit_f64(f),
+            Value::U8(u) => visitor.visit_u8(u),
+            Value::U16(u) => visitor.visit_u16(u),
+            Value::I16(i) => visitor.visit_i16(i),
+            Value::U32(u) => visitor.visit_u32(u),
+            Value::U64(u) => visitor.visit_u64(u),
+            Value::I64(i) => visitor.visit_i64(i),
+            Value::F32(f) => visitor.visit_f32(f),
+            Value::List(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter().map(ValueDeserializer));
+                let result = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(result)
+            }
+            Value::Record(record) => RecordDeserializer(record).deserialize_any(visitor),
+        }
+    }
+
+    // Like the `FromParsed` impls, "true" is a bool and a byte a char
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            Value::Str(ref s) if s == "true" => visitor.visit_bool(true),
+            Value::Str(ref s) if s == "false" => visitor.visit_bool(false),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.0 {
+            Value::U8(b) => visitor.visit_char(b as char),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    // A captured value is always there, missing ones never get here
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Unit variants by their names, like "ATOM" into `Kind::ATOM`
+    fn deserialize_enum<V: Visitor<'de>>(self,
+                                         _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V)
+                                         -> Result<V::Value, DeError> {
+        match self.0 {
+            Value::Str(s) => visitor.visit_enum(s.into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use dsl::Spec;
+
+    // Variants are spelled like the record names in the file
+    #[allow(clippy::upper_case_acronyms)]
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Kind {
+        ATOM,
+        HETATM,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Position {
+        x: f64,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Atom {
+        kind: Kind,
+        serial: u32,
+        name: String,
+        alt_loc: Option<char>,
+        #[serde(default)]
+        position: Vec<Position>,
+    }
+
+    #[test]
+    fn deserialize_test() {
+        let spec = Spec::compile(r#"
+            take 6 -> trimr -> capture kind
+            take 5 -> trim -> to_i32 -> capture serial
+            skip 1
+            take 4 -> trim -> capture name
+            take 1 -> opt(neq " " -> to_byte -> capture alt_loc)
+            skip 13
+            (take 8 -> trim -> to_f64 -> capture x)* as position
+        "#).unwrap();
+        let parser = spec.parser();
+
+        let line = "HETATM   23  CG1 VAL R   3      58.836  39.331";
+        let (atom, rest): (Atom, _) = parse(&parser, line).unwrap();
+        assert_eq!(Some(""), rest);
+        assert_eq!(Atom {
+            kind: Kind::HETATM,
+            serial: 23,
+            name: "CG1".to_string(),
+            alt_loc: None,
+            position: vec![Position { x: 58.836 }, Position { x: 39.331 }],
+        }, atom);
+
+        let (atom, _): (Atom, _) = parse(&parser, "ATOM      1  N  AMET A   1    ").unwrap();
+        assert_eq!((Kind::ATOM, Some('A'), vec![]), (atom.kind, atom.alt_loc, atom.position));
+
+        match parse::<Atom, _>(&parser, "ATOM      x") {
+            Err(DeError::Parse(_)) => (),
+            _ => panic!("the parse fails before any deserializing"),
+        }
+        let e = parse::<Atom, _>(&parser, "OTHER     1  N   MET A   1    ").unwrap_err();
+        assert!(e.to_string().starts_with("unknown variant `OTHER`"));
+    }
+}
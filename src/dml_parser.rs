@@ -1,34 +1,44 @@
-use parser::{Parser, word, blank, tag};
+use parser::{Parser, seq, pword, blank0, balanced};
+use chain::{store_at, trim};
 
-struct DefUse {
-    name: String,
-    data: String,
+/// `def use NAME {{ DATA }}`, code kept under a name for blocks to use.
+/// The data can hold `{{ }}` pairs of its own.
+#[derive(Debug, Default, PartialEq)]
+pub struct DefUse {
+    pub name: String,
+    pub data: String,
 }
 
 impl DefUse {
-    pub fn parse_def_use(du: &mut DefUse) -> Parser {
-        seq([
-            word().store(du.name),
-            blank(),
-            tag("{{"),
-            //until("}}", ["\}}"]).store(du.data)
+    /// Parser for what follows `def use`, it stores through `parse_into`
+    pub fn parser() -> Parser<'static, 'static> {
+        seq(vec![
+            pword().chain(store_at(|du: &mut DefUse| &mut du.name)),
+            blank0(),
+            balanced("{{", "}}").chain(trim().store_at(|du: &mut DefUse| &mut du.data)),
         ])
     }
 }
-// fn parse_def() {
-//     let def = 
-//         or([
-//             seq([
-//                 tag("def"),
-//                 blank(),
-//                 or([
-//                     seq([tag("use"), blank(), parse_def_use()]),
-//                     seq([tag("block"), blank(), parse_def_block()]),
-//                 ])
-//             ])
-//         ]);
-//     seq([
-//         blank(),
-        
-//     ])
-// }
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{preceded, tag, blank1};
+
+    #[test]
+    fn def_use_test() {
+        let dml = "def use core {{
+    extern crate glutin;
+    let block = {{ x }};
+}}
+
+use core";
+        let parser = preceded(seq(vec![tag("def"), blank1(), tag("use"), blank1()]), DefUse::parser());
+        assert!(parser.validate().is_ok());
+
+        let mut du = DefUse::default();
+        assert_eq!(Some("\n\nuse core"), parser.parse_into(dml, &mut du).unwrap());
+        assert_eq!("core", du.name);
+        assert_eq!("extern crate glutin;\n    let block = {{ x }};", du.data);
+    }
+}
@@ -0,0 +1,584 @@
+use std::error::Error;
+use std::fmt;
+use parser::{Parser, ParseError, ParsingError, ValidationError, seq, or, many0, many1, count, opt, peek, not,
+             delimited, take, skip, tag, pword, until, balanced, rule, blank0, blank1, space0, space1, line_ending};
+use chain::{self, Chain, Endian, Binary, eq_str, neq_str, capture};
+use grammar::Grammar;
+use record::Record;
+use value::Value;
+
+/// A grammar written as text, so record layouts can live in
+/// configuration files. It reads like `inspect::print`:
+///
+/// ```text
+/// take 6 -> trimr -> eq "ATOM"
+/// take 5 -> trim -> to_i32 -> capture serial
+/// skip 1
+/// (tag "," pword -> capture name)* as names
+/// ```
+///
+/// Parsers in a row are a sequence, `a | b` are alternatives, `( )`
+/// groups, a parser followed by `*`, `+` or `{3}` repeats, by `?` is
+/// optional and by `as name` puts its captures in a record of their own.
+/// Parsers are take N, skip N, tag "..", pword, blank, blank0, blank1,
+/// space0, space1, line_ending, until ".." escape "..", balanced ".." "..",
+/// rule name for the rules of a `Grammar` and the lookaheads peek ( .. )
+/// and not ( .. ). Chains take the
+/// operations of `chain` by their names, plus `opt ( .. )`,
+/// `or ( .. | .. )` and `capture name`. Names are words like `pword`
+/// reads, `#` starts a comment.
+///
+/// The language is read by a `Grammar` of this crate's own parsers, its
+/// captures are then built into the parser.
+pub struct Spec {
+    root: Node,
+}
+
+impl Spec {
+    /// Reads `source` and checks the parser it describes
+    pub fn compile(source: &str) -> Result<Spec, DslError> {
+        let grammar = grammar();
+        let (record, rest) = match grammar.parse_record("spec", source) {
+            Ok((record, rest)) => (record, rest.unwrap_or("")),
+            Err(e) => return Err(syntax_error(source, farthest(&e))),
+        };
+        let root = match record.record("root") {
+            Some(root) if rest.is_empty() => alternatives(root)?,
+            _ => {
+                let read = source.len() - rest.len();
+                let offset = match grammar.parse("resume", rest) {
+                    Err(e) => read + farthest(&e),
+                    Ok(_) => read,
+                };
+                return Err(syntax_error(source, offset));
+            }
+        };
+        let spec = Spec { root };
+        if let Err(errors) = spec.parser().validate() {
+            return Err(DslError::Invalid(errors));
+        }
+        Ok(spec)
+    }
+
+    /// The parser, its captures come back from `parse_record`
+    pub fn parser(&self) -> Parser<'static, '_> {
+        build(&self.root)
+    }
+}
+
+#[derive(Debug)]
+pub enum DslError {
+    // The text doesn't read at this line and column, holds what is
+    // there or None at the end
+    Syntax(usize, usize, Option<String>),
+    // Expected the first, found the second
+    Expected(&'static str, Option<String>),
+    // The grammar reads fine but the parser it makes doesn't validate
+    Invalid(Vec<ValidationError>),
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DslError::Syntax(line, column, Some(ref found)) => {
+                write!(f, "unexpected `{}` at line {}, column {}", found, line, column)
+            }
+            DslError::Syntax(line, column, None) => write!(f, "unexpected end at line {}, column {}", line, column),
+            DslError::Expected(expected, Some(ref found)) => write!(f, "expected {}, found `{}`", expected, found),
+            DslError::Expected(expected, None) => write!(f, "expected {}, found the end", expected),
+            DslError::Invalid(ref errors) => {
+                write!(f, "invalid parser:")?;
+                for e in errors {
+                    write!(f, " {};", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for DslError {}
+
+struct Node {
+    kind: Kind,
+    name: Option<String>,
+    ops: Vec<Op>,
+}
+
+enum Kind {
+    Seq(Vec<Node>),
+    Or(Vec<Node>),
+    Many0(Box<Node>),
+    Many1(Box<Node>),
+    Count(usize, Box<Node>),
+    Opt(Box<Node>),
+    Peek(Box<Node>),
+    Not(Box<Node>),
+    Take(usize),
+    Skip(usize),
+    Tag(String),
+    PWord,
+    Blank0,
+    Blank1,
+    Space0,
+    Space1,
+    LineEnding,
+    Until(Vec<String>, Vec<String>),
+    Balanced(String, String),
+    Rule(String),
+}
+
+impl Node {
+    fn new(kind: Kind) -> Node {
+        Node { kind, name: None, ops: vec![] }
+    }
+}
+
+enum Op {
+    Trim,
+    TrimL,
+    TrimR,
+    Eq(String),
+    Neq(String),
+    Empty,
+    ToI32,
+    ToF64,
+    ToU8,
+    ToByte,
+    Bytes,
+    Decode(Endian, Binary),
+    Capture(String),
+    Opt(Vec<Op>),
+    Or(Vec<Vec<Op>>),
+}
+
+// Anything else ends a word
+const DELIMITERS: [&str; 15] = [" ", "\t", "\r", "\n", "(", ")", "|", "*", "+", "?", "{", "}", "\"", "->", "#"];
+
+const PARSERS: [&str; 7] = ["pword", "blank", "blank0", "blank1", "space0", "space1", "line_ending"];
+
+const OPS: [&str; 9] = ["trim", "triml", "trimr", "empty", "to_i32", "to_f64", "to_u8", "to_byte", "bytes"];
+
+// Blanks and comments
+fn ws() -> Parser<'static, 'static> {
+    many0(or(vec![blank1(), seq(vec![tag("#"), until(&["\n"], &[])])]))
+}
+
+fn word(name: &str) -> Parser<'static, 'static> {
+    until(&DELIMITERS, &[]).chain(neq_str("").capture(name))
+}
+
+// The raw text between the quotes, unescaped once the spec is read
+fn string(name: &str) -> Parser<'static, 'static> {
+    delimited(tag("\""), until(&["\""], &["\\\"", "\\\\"]).chain(capture(name)), tag("\""))
+}
+
+fn keyword(word: &str) -> Parser<'static, 'static> {
+    pword().chain(eq_str(word))
+}
+
+// A keyword captured as the kind of what it starts
+fn kind(word: &str) -> Parser<'static, 'static> {
+    pword().chain(eq_str(word).capture("kind"))
+}
+
+fn one_of<S: AsRef<str>>(words: &[S]) -> Parser<'static, 'static> {
+    pword().chain(chain::or(words.iter().map(|w| eq_str(w.as_ref()).capture("kind")).collect()))
+}
+
+// `( alternatives )` after peek and not
+fn group() -> Parser<'static, 'static> {
+    seq(vec![tag("("), ws(), rule("alt").named("inner"), ws(), tag(")")])
+}
+
+// The language, read into a record of captures
+fn grammar() -> Grammar<'static, 'static> {
+    let decoders: Vec<String> = ["le_", "be_"].iter()
+        .flat_map(|e| ["u16", "i16", "u32", "i32", "u64", "i64", "f32", "f64"].iter().map(move |b| format!("{}{}", e, b)))
+        .collect();
+
+    Grammar::new()
+        .define("spec", seq(vec![ws(), opt(rule("alt").named("root")), ws()]))
+        .define("alt", seq(vec![
+            rule("seq").named("alt"),
+            many0(seq(vec![ws(), tag("|"), ws(), rule("seq").named("alt")])),
+        ]))
+        .define("seq", seq(vec![
+            rule("item").named("item"),
+            many0(seq(vec![ws(), rule("item").named("item")])),
+        ]))
+        .define("item", seq(vec![
+            rule("atom"),
+            opt(seq(vec![ws(), or(vec![
+                tag("*").chain(capture("repeat")),
+                tag("+").chain(capture("repeat")),
+                tag("?").chain(capture("repeat")),
+                seq(vec![tag("{"), ws(), word("count"), ws(), tag("}")]),
+            ])])),
+            opt(seq(vec![ws(), keyword("as"), ws(), pword().chain(capture("as"))])),
+            many0(seq(vec![ws(), tag("->"), ws(), rule("op").named("op")])),
+        ]))
+        .define("atom", or(vec![
+            seq(vec![tag("(").chain(capture("kind")), ws(), rule("alt").named("inner"), ws(), tag(")")]),
+            seq(vec![kind("peek"), ws(), group()]),
+            seq(vec![kind("not"), ws(), group()]),
+            seq(vec![kind("take"), ws(), word("number")]),
+            seq(vec![kind("skip"), ws(), word("number")]),
+            seq(vec![kind("tag"), ws(), string("string")]),
+            seq(vec![kind("balanced"), ws(), string("open"), ws(), string("close")]),
+            seq(vec![
+                kind("until"),
+                many1(seq(vec![ws(), string("string")])),
+                opt(seq(vec![ws(), keyword("escape"), many1(seq(vec![ws(), string("escape")]))])),
+            ]),
+            seq(vec![kind("rule"), ws(), pword().chain(capture("name"))]),
+            one_of(&PARSERS),
+        ]))
+        .define("op", or(vec![
+            seq(vec![kind("eq"), ws(), string("string")]),
+            seq(vec![kind("neq"), ws(), string("string")]),
+            seq(vec![kind("capture"), ws(), pword().chain(capture("name"))]),
+            seq(vec![kind("opt"), ws(), tag("("), ws(), rule("ops").named("inner"), ws(), tag(")")]),
+            seq(vec![
+                kind("or"), ws(), tag("("), ws(),
+                rule("ops").named("branch"),
+                many0(seq(vec![ws(), tag("|"), ws(), rule("ops").named("branch")])),
+                ws(), tag(")"),
+            ]),
+            one_of(&OPS),
+            one_of(&decoders),
+        ]))
+        // The ops inside opt and or, the first one has no arrow
+        .define("ops", seq(vec![
+            rule("op").named("op"),
+            many0(seq(vec![ws(), tag("->"), ws(), rule("op").named("op")])),
+        ]))
+        // Whatever could go on where a spec stopped, its error says why
+        // it couldn't
+        .define("resume", or(vec![
+            seq(vec![tag("|"), ws(), rule("item")]),
+            seq(vec![tag("->"), ws(), rule("op")]),
+            rule("item"),
+        ]))
+}
+
+// Where the parse got furthest before failing
+fn farthest(e: &ParseError) -> usize {
+    match e.kind {
+        ParsingError::NoAlternative(ref errors) => errors.iter().map(farthest).fold(e.span.offset, usize::max),
+        _ => e.span.offset,
+    }
+}
+
+fn syntax_error(source: &str, offset: usize) -> DslError {
+    let read = &source[..offset];
+    let line = read.matches('\n').count() + 1;
+    let column = read.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    let rest = &source[offset..];
+    let found = match rest.chars().next() {
+        None => None,
+        Some(c) => match rest.find(|c: char| DELIMITERS.iter().any(|d| d.starts_with(c))) {
+            Some(0) if rest.starts_with("->") => Some("->".to_string()),
+            Some(0) => Some(c.to_string()),
+            Some(end) => Some(rest[..end].to_string()),
+            None => Some(rest.to_string()),
+        },
+    };
+    DslError::Syntax(line, column, found)
+}
+
+fn unescape(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some('t') => text.push('\t'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+// A capture made once is a record, made again it becomes a list
+fn each<'r>(record: &'r Record, name: &str) -> Vec<&'r Record> {
+    match record.value(name) {
+        Some(Value::Record(r)) => vec![r],
+        Some(Value::List(_)) => record.records(name).collect(),
+        _ => vec![],
+    }
+}
+
+fn strings(record: &Record, name: &str) -> Vec<String> {
+    match record.value(name) {
+        Some(Value::Str(s)) => vec![unescape(s)],
+        Some(Value::List(values)) => values.iter().filter_map(|v| match *v {
+            Value::Str(ref s) => Some(unescape(s)),
+            _ => None,
+        }).collect(),
+        _ => vec![],
+    }
+}
+
+fn string_at(record: &Record, name: &str) -> String {
+    record.str(name).map(unescape).unwrap_or_default()
+}
+
+fn name_at(record: &Record, name: &str) -> String {
+    record.str(name).unwrap_or_default().to_string()
+}
+
+fn number(found: Option<&str>) -> Result<usize, DslError> {
+    match found {
+        Some(t) => match t.parse() {
+            Ok(n) => Ok(n),
+            Err(_) => Err(DslError::Expected("a number", Some(t.to_string()))),
+        },
+        None => Err(DslError::Expected("a number", None)),
+    }
+}
+
+fn inner(record: &Record) -> Result<Node, DslError> {
+    match record.record("inner") {
+        Some(inner) => alternatives(inner),
+        None => Err(DslError::Expected("a parser", None)),
+    }
+}
+
+fn alternatives(record: &Record) -> Result<Node, DslError> {
+    let mut alternatives = vec![];
+    for alt in each(record, "alt") {
+        let mut items = vec![];
+        for i in each(alt, "item") {
+            items.push(item(i)?);
+        }
+        alternatives.push(if items.len() == 1 { items.remove(0) } else { Node::new(Kind::Seq(items)) });
+    }
+    if alternatives.len() == 1 {
+        return Ok(alternatives.remove(0));
+    }
+    Ok(Node::new(Kind::Or(alternatives)))
+}
+
+fn item(record: &Record) -> Result<Node, DslError> {
+    let mut node = atom(record)?;
+    node = match record.str("repeat") {
+        Some("*") => Node::new(Kind::Many0(Box::new(node))),
+        Some("+") => Node::new(Kind::Many1(Box::new(node))),
+        Some("?") => Node::new(Kind::Opt(Box::new(node))),
+        _ => node,
+    };
+    if record.value("count").is_some() {
+        node = Node::new(Kind::Count(number(record.str("count"))?, Box::new(node)));
+    }
+    node.name = record.str("as").map(|name| name.to_string());
+    // A chain on a group is kept for validate to report
+    node.ops = ops(record)?;
+    Ok(node)
+}
+
+fn atom(record: &Record) -> Result<Node, DslError> {
+    let kind = match record.str("kind") {
+        Some("(") => {
+            let node = inner(record)?;
+            // (a) -> trim must not chain onto what a already has
+            if !node.ops.is_empty() {
+                return Ok(Node::new(Kind::Seq(vec![node])));
+            }
+            return Ok(node);
+        }
+        Some("peek") => Kind::Peek(Box::new(inner(record)?)),
+        Some("not") => Kind::Not(Box::new(inner(record)?)),
+        Some("take") => Kind::Take(number(record.str("number"))?),
+        Some("skip") => Kind::Skip(number(record.str("number"))?),
+        Some("tag") => Kind::Tag(string_at(record, "string")),
+        Some("pword") => Kind::PWord,
+        Some("blank") | Some("blank0") => Kind::Blank0,
+        Some("blank1") => Kind::Blank1,
+        Some("space0") => Kind::Space0,
+        Some("space1") => Kind::Space1,
+        Some("line_ending") => Kind::LineEnding,
+        Some("balanced") => Kind::Balanced(string_at(record, "open"), string_at(record, "close")),
+        Some("rule") => Kind::Rule(name_at(record, "name")),
+        Some("until") => Kind::Until(strings(record, "string"), strings(record, "escape")),
+        found => return Err(DslError::Expected("a parser", found.map(|t| t.to_string()))),
+    };
+    Ok(Node::new(kind))
+}
+
+fn ops(record: &Record) -> Result<Vec<Op>, DslError> {
+    each(record, "op").into_iter().map(op).collect()
+}
+
+fn op(record: &Record) -> Result<Op, DslError> {
+    let op = match record.str("kind") {
+        Some("trim") => Op::Trim,
+        Some("triml") => Op::TrimL,
+        Some("trimr") => Op::TrimR,
+        Some("eq") => Op::Eq(string_at(record, "string")),
+        Some("neq") => Op::Neq(string_at(record, "string")),
+        Some("empty") => Op::Empty,
+        Some("to_i32") => Op::ToI32,
+        Some("to_f64") => Op::ToF64,
+        Some("to_u8") => Op::ToU8,
+        Some("to_byte") => Op::ToByte,
+        Some("bytes") => Op::Bytes,
+        Some("capture") => Op::Capture(name_at(record, "name")),
+        Some("opt") => match record.record("inner") {
+            Some(inner) => Op::Opt(ops(inner)?),
+            None => Op::Opt(vec![]),
+        },
+        Some("or") => Op::Or(each(record, "branch").into_iter().map(ops).collect::<Result<_, _>>()?),
+        found => match found.and_then(decoder) {
+            Some((endian, binary)) => Op::Decode(endian, binary),
+            None => return Err(DslError::Expected("a chain operation", found.map(|t| t.to_string()))),
+        },
+    };
+    Ok(op)
+}
+
+// le_u16, be_f64 ...
+fn decoder(name: &str) -> Option<(Endian, Binary)> {
+    let endian = if name.starts_with("le_") {
+        Endian::Little
+    } else if name.starts_with("be_") {
+        Endian::Big
+    } else {
+        return None;
+    };
+    let binary = match &name[3..] {
+        "u16" => Binary::U16,
+        "i16" => Binary::I16,
+        "u32" => Binary::U32,
+        "i32" => Binary::I32,
+        "u64" => Binary::U64,
+        "i64" => Binary::I64,
+        "f32" => Binary::F32,
+        "f64" => Binary::F64,
+        _ => return None,
+    };
+    Some((endian, binary))
+}
+
+fn build(node: &Node) -> Parser<'static, '_> {
+    let parser = match node.kind {
+        Kind::Seq(ref nodes) => seq(nodes.iter().map(build).collect()),
+        Kind::Or(ref nodes) => or(nodes.iter().map(build).collect()),
+        Kind::Many0(ref node) => many0(build(node)),
+        Kind::Many1(ref node) => many1(build(node)),
+        Kind::Count(times, ref node) => count(times, build(node)),
+        Kind::Opt(ref node) => opt(build(node)),
+        Kind::Peek(ref node) => peek(build(node)),
+        Kind::Not(ref node) => not(build(node)),
+        Kind::Take(n) => take(n),
+        Kind::Skip(n) => skip(n),
+        Kind::Tag(ref value) => tag(value.as_str()),
+        Kind::PWord => pword(),
+        Kind::Blank0 => blank0(),
+        Kind::Blank1 => blank1(),
+        Kind::Space0 => space0(),
+        Kind::Space1 => space1(),
+        Kind::LineEnding => line_ending(),
+        Kind::Until(ref terminators, ref escapes) => {
+            let terminators: Vec<&str> = terminators.iter().map(|t| t.as_str()).collect();
+            let escapes: Vec<&str> = escapes.iter().map(|e| e.as_str()).collect();
+            until(&terminators, &escapes)
+        }
+        Kind::Balanced(ref open, ref close) => balanced(open, close),
+        Kind::Rule(ref name) => rule(name),
+    };
+    let parser = match node.name {
+        Some(ref name) => parser.named(name),
+        None => parser,
+    };
+    if node.ops.is_empty() {
+        return parser;
+    }
+    parser.chain(build_chain(&node.ops))
+}
+
+fn build_chain(ops: &[Op]) -> Chain<'static> {
+    ops.iter().fold(Chain::new(), |chain, op| match *op {
+        Op::Trim => chain.trim(),
+        Op::TrimL => chain.triml(),
+        Op::TrimR => chain.trimr(),
+        Op::Eq(ref s) => chain.eq_str(s),
+        Op::Neq(ref s) => chain.neq_str(s),
+        Op::Empty => chain.empty(),
+        Op::ToI32 => chain.to_i32(),
+        Op::ToF64 => chain.to_f64(),
+        Op::ToU8 => chain.to_u8(),
+        Op::ToByte => chain.to_byte(),
+        Op::Bytes => chain.bytes(),
+        Op::Decode(endian, binary) => chain.decode(endian, binary),
+        Op::Capture(ref name) => chain.capture(name),
+        Op::Opt(ref ops) => chain.opt(build_chain(ops)),
+        Op::Or(ref chains) => chain.or(chains.iter().map(|ops| build_chain(ops)).collect()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grammar::Grammar;
+
+    #[test]
+    fn compile_test() {
+        let spec = Spec::compile(r#"
+            # ATOM and HETATM lines, up to the coordinates
+            take 6 -> trimr -> or(eq "ATOM" | eq "HETATM")
+            take 5 -> trim -> to_i32 -> capture serial
+            skip 1
+            take 4 -> trim -> capture name
+            take 1 -> opt(to_u8 -> capture alt_loc)
+            take 3 -> capture res_name
+            skip 10
+            (take 8 -> trim -> to_f64 -> capture x){2} as position
+            until "\n" escape "\\\n"
+        "#).unwrap();
+        let parser = spec.parser();
+
+        let line = "HETATM   23  CG1 VAL R   3      58.836  39.331";
+        let (record, rest) = parser.parse_record(line).unwrap();
+        assert_eq!(Some(""), rest);
+        assert_eq!(Some(23), record.i32("serial"));
+        assert_eq!(Some("CG1"), record.str("name"));
+        assert_eq!(vec![Some(58.836), Some(39.331)], record.records("position").map(|p| p.f64("x")).collect::<Vec<_>>());
+        assert_eq!(None, record.value("alt_loc"));
+
+        let spec = Spec::compile(r#"(tag "a" | tag "\"")+ tag "," (pword space0){2}"#).unwrap();
+        assert_eq!(Some("d"), spec.parser().parse("a\"a,ab c d").unwrap());
+
+        let spec = Spec::compile(r#"(not(tag "def") pword space0)+ tag "def"? pword?"#).unwrap();
+        assert_eq!(Some(" x"), spec.parser().parse("use core def x").unwrap());
+
+        let spec = Spec::compile(r#"pword -> capture name space0 balanced "{{" "}}" -> trim -> capture data"#).unwrap();
+        let (record, _) = spec.parser().parse_record("core {{ a {{ b }} }}").unwrap();
+        assert_eq!((Some("core"), Some("a {{ b }}")), (record.str("name"), record.str("data")));
+
+        let spec = Spec::compile(r#"tag "(" (rule list | pword -> capture atom | space1)* tag ")""#).unwrap();
+        let grammar = Grammar::new().define("list", spec.parser());
+        assert!(grammar.validate().is_ok());
+        assert_eq!(Some(""), grammar.parse("list", "(a (b (c)) d)").unwrap());
+    }
+
+    #[test]
+    fn compile_error_test() {
+        let error = |source| Spec::compile(source).err().unwrap().to_string();
+        assert_eq!("expected a number, found `x`", error("take x"));
+        assert_eq!("expected a number, found `x`", error("take 1 (pword){x}"));
+        assert_eq!("unexpected `to_i8` at line 1, column 11", error("take 1 -> to_i8"));
+        assert_eq!("unexpected end at line 1, column 17", error("(take 1 | skip 2"));
+        assert_eq!("unexpected end at line 2, column 7", error("pword\ntag \"x"));
+        assert_eq!("unexpected `)` at line 1, column 7", error("pword ) tag \"x\""));
+        assert_eq!("unexpected `words` at line 3, column 13", error("pword\n  # comment\n  tag \"a\" | words"));
+        assert_eq!("unexpected end at line 1, column 1", error(""));
+        assert_eq!("invalid parser: sequence cannot have a chain at [];", error("(take 1 skip 1) -> trim"));
+    }
+}
@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::fmt;
+use parser::Parser;
+pub use super_parser_derive::FixedWidth;
+
+/// Records laid out in fixed columns, like the lines of a PDB file.
+/// Usually derived, `#[col(31..=38, f64, trim)]` on a field reads columns
+/// 31 to 38 (1-based, inclusive) into it.
+pub trait FixedWidth {
+    /// Parser for one record, it stores through `parse_into`
+    fn parser() -> Parser<'static, 'static>;
+
+    /// The record back in its columns
+    fn to_fixed(&self) -> Result<String, WidthError>;
+}
+
+/// A value that doesn't fit its columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct WidthError {
+    pub field: &'static str,
+    pub width: usize,
+    pub value: String,
+}
+
+impl fmt::Display for WidthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` doesn't fit the {} columns of {}", self.value, self.width, self.field)
+    }
+}
+
+impl Error for WidthError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Writes `value` into `width` columns starting at the 0-based `start`,
+/// padding with spaces
+pub fn write_column(line: &mut String,
+                    start: usize,
+                    width: usize,
+                    value: &str,
+                    align: Align,
+                    field: &'static str)
+                    -> Result<(), WidthError> {
+    let len = value.chars().count();
+    if len > width {
+        return Err(WidthError { field, width, value: value.to_string() });
+    }
+    let written = line.chars().count();
+    if written < start {
+        line.extend((written..start).map(|_| ' '));
+    }
+    match align {
+        Align::Left => {
+            line.push_str(value);
+            line.extend((len..width).map(|_| ' '));
+        }
+        Align::Right => {
+            line.extend((len..width).map(|_| ' '));
+            line.push_str(value);
+        }
+    }
+    Ok(())
+}
+
+/// Cuts the blanks off the end of `line`, keeping at least `required`
+/// columns
+pub fn trim_tail(line: &mut String, required: usize) {
+    let kept = line.trim_end().chars().count().max(required);
+    if let Some((i, _)) = line.char_indices().nth(kept) {
+        line.truncate(i);
+    }
+}
+
+/// How a field is written into its columns
+pub trait Column {
+    /// `precision` is the number of decimals, only used by floats
+    fn column(&self, precision: Option<usize>) -> String;
+}
+
+macro_rules! display_columns {
+    ($($t:ty),*) => {$(
+        impl Column for $t {
+            fn column(&self, _precision: Option<usize>) -> String {
+                self.to_string()
+            }
+        }
+    )*};
+}
+
+display_columns!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, String, char, bool);
+
+impl Column for f64 {
+    fn column(&self, precision: Option<usize>) -> String {
+        match precision {
+            Some(p) => format!("{:.*}", p, self),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl Column for f32 {
+    fn column(&self, precision: Option<usize>) -> String {
+        match precision {
+            Some(p) => format!("{:.*}", p, self),
+            None => self.to_string(),
+        }
+    }
+}
+
+// None leaves the columns blank
+impl<T: Column> Column for Option<T> {
+    fn column(&self, precision: Option<usize>) -> String {
+        match *self {
+            Some(ref t) => t.column(precision),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(FixedWidth, Default, Debug, PartialEq)]
+    #[col(1..=6, eq = "HETATM")]
+    struct Hetatm {
+        #[col(7..=11, i32, trim)]
+        serial: i32,
+        #[col(13..=16, trim)]
+        name: String,
+        #[col(22, byte)]
+        chain_id: u8,
+        #[col(31..=38, f64, trim, precision = 3)]
+        x: f64,
+        #[col(39..=46, f64, trim, opt, tail, precision = 3)]
+        y: Option<f64>,
+    }
+
+    #[test]
+    fn derive_test() {
+        let line = "HETATM 1234 ZN   ZN  A 401      12.500  -3.250";
+        let parser = Hetatm::parser();
+        assert!(parser.validate().is_ok());
+
+        let mut hetatm = Hetatm::default();
+        assert!(parser.parse_into(line, &mut hetatm).is_ok());
+        assert_eq!(Hetatm {
+            serial: 1234,
+            name: "ZN".to_string(),
+            chain_id: b'A',
+            x: 12.5,
+            y: Some(-3.25),
+        }, hetatm);
+
+        assert_eq!("HETATM 1234 ZN       A          12.500  -3.250", hetatm.to_fixed().unwrap());
+
+        let mut short = Hetatm::default();
+        assert_eq!(Some(""), parser.parse_into(&line[..38], &mut short).unwrap());
+        assert_eq!((12.5, None), (short.x, short.y));
+
+        hetatm.serial = 123456;
+        let e = hetatm.to_fixed().unwrap_err();
+        assert_eq!(("serial", 5), (e.field, e.width));
+    }
+}
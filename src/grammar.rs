@@ -0,0 +1,293 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use input::Input;
+use parser::{Parser, ParserType, ParseError, ParsingError, InvalidParserError, ValidationError, Span};
+use record::Record;
+
+/// How many rules can run inside each other before the parse fails.
+/// Deep enough for real files and shallow enough for a 2MB thread stack
+/// in debug builds, grammars with long chains of parsers between two
+/// rules may need a lower `depth`.
+pub const DEFAULT_DEPTH_LIMIT: usize = 64;
+
+/// Parsers by name, for grammars that refer to themselves. A `rule`
+/// parser is looked up when it runs, so a rule can use rules defined
+/// after it, or itself:
+///
+/// ```text
+/// Grammar::new().define("block", seq(vec![
+///     tag("{"),
+///     many0(or(vec![rule("block"), pword(), blank1()])),
+///     tag("}"),
+/// ]))
+/// ```
+pub struct Grammar<'c, 't> {
+    rules: HashMap<String, Parser<'c, 't>>,
+    depth_limit: usize,
+    memoize: bool,
+    memo_stats: Cell<MemoStats>,
+}
+
+/// How the memo of a parse did, see `Grammar::memoize`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MemoStats {
+    /// Rules and ors that didn't run again, their result was known
+    pub hits: usize,
+    /// Rules and ors that ran
+    pub misses: usize,
+}
+
+impl<'c, 't> Default for Grammar<'c, 't> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c, 't> Grammar<'c, 't> {
+    pub fn new() -> Grammar<'c, 't> {
+        Grammar {
+            rules: HashMap::new(),
+            depth_limit: DEFAULT_DEPTH_LIMIT,
+            memoize: false,
+            memo_stats: Cell::new(MemoStats::default()),
+        }
+    }
+
+    /// Adds the rule `name`, replacing one of the same name
+    pub fn define(mut self, name: &str, parser: Parser<'c, 't>) -> Self {
+        self.rules.insert(name.to_string(), parser);
+        self
+    }
+
+    /// Input nested deeper than this fails with `ParsingError::TooDeep`
+    pub fn depth(mut self, limit: usize) -> Self {
+        self.depth_limit = limit;
+        self
+    }
+
+    pub fn depth_limit(&self) -> usize {
+        self.depth_limit
+    }
+
+    /// Remembers what each rule and or did at each offset, so trying them
+    /// again there costs nothing. Grammars that backtrack a lot go from
+    /// exponential to linear time, at the cost of memory for the results.
+    /// Stores and captures are remembered, a stored value is converted for
+    /// its field again when it is replayed. Only rules reach the same parser
+    /// twice at one offset, so parsers run without a grammar don't memoize.
+    pub fn memoize(mut self, on: bool) -> Self {
+        self.memoize = on;
+        self
+    }
+
+    pub fn memoizes(&self) -> bool {
+        self.memoize
+    }
+
+    /// Hits and misses of the memo in the last parse
+    pub fn memo_stats(&self) -> MemoStats {
+        self.memo_stats.get()
+    }
+
+    pub(crate) fn set_memo_stats(&self, stats: MemoStats) {
+        self.memo_stats.set(stats);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Parser<'c, 't>> {
+        self.rules.get(name)
+    }
+
+    /// Validates every rule and checks the rules they refer to exist.
+    /// Errors come with the name of the rule they are in.
+    pub fn validate(&self) -> Result<(), Vec<(String, ValidationError)>> {
+        let mut errors = vec![];
+        let mut names: Vec<&String> = self.rules.keys().collect();
+        names.sort();
+        for name in names {
+            let parser = &self.rules[name];
+            if let Err(found) = parser.validate() {
+                errors.extend(found.into_iter().map(|e| (name.clone(), e)));
+            }
+            let mut path = vec![];
+            self.check_rules(name, parser, &mut path, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn check_rules(&self, name: &str, parser: &Parser, path: &mut Vec<usize>, errors: &mut Vec<(String, ValidationError)>) {
+        if let ParserType::Rule(used) = parser.ptype {
+            if !self.rules.contains_key(used) {
+                let kind = InvalidParserError::UnknownRule(used.to_string());
+                errors.push((name.to_string(), ValidationError { kind, path: path.clone() }));
+            }
+        }
+        for (i, child) in parser.children().into_iter().enumerate() {
+            path.push(i);
+            self.check_rules(name, child, path, errors);
+            path.pop();
+        }
+    }
+
+    /// Parses `buffer` with the rule `name`, like `Parser::parse`
+    pub fn parse<'d, I: Input<'d>>(&self, name: &str, buffer: I) -> Result<Option<I>, ParseError> {
+        self.parse_with(name, buffer, false, None).map(|(rest, _)| rest)
+    }
+
+    pub fn parse_partial<'d, I: Input<'d>>(&self, name: &str, buffer: I) -> Result<Option<I>, ParseError> {
+        self.parse_with(name, buffer, true, None).map(|(rest, _)| rest)
+    }
+
+    pub fn parse_into<'d, I: Input<'d>, T: Any>(&self, name: &str, buffer: I, output: &mut T) -> Result<Option<I>, ParseError> {
+        self.parse_with(name, buffer, false, Some(output)).map(|(rest, _)| rest)
+    }
+
+    pub fn parse_record<'d, I: Input<'d>>(&self, name: &str, buffer: I) -> Result<(Record, Option<I>), ParseError> {
+        self.parse_with(name, buffer, false, None).map(|(rest, record)| (record, rest))
+    }
+
+    fn parse_with<'d, I: Input<'d>>(&self,
+                                    name: &str,
+                                    buffer: I,
+                                    partial: bool,
+                                    output: Option<&mut dyn Any>)
+                                    -> Result<(Option<I>, Record), ParseError> {
+        match self.rules.get(name) {
+            Some(parser) => parser.parse_with(buffer, partial, output, Some(self)),
+            None => Err(ParseError {
+                kind: ParsingError::UnknownRule(name.to_string()),
+                span: Span { offset: 0, len: 0, line: 1, column: 1 },
+                path: vec![],
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::{capture, push};
+    use parser::{seq, or, many0, tag, pword, blank0, rule};
+
+    fn blocks<'c>() -> Grammar<'c, 'static> {
+        Grammar::new().define("block", seq(vec![
+            tag("{"),
+            blank0(),
+            many0(seq(vec![
+                or(vec![rule("block").named("block"), pword().chain(capture("word"))]),
+                blank0(),
+            ])),
+            tag("}"),
+        ]))
+    }
+
+    #[test]
+    fn recursive_test() {
+        let grammar = blocks();
+        assert!(grammar.validate().is_ok());
+
+        let (record, rest) = grammar.parse_record("block", "{ a { b { c } } d }").unwrap();
+        assert_eq!(Some(""), rest);
+        assert_eq!(Some(vec!["a".to_string(), "d".to_string()]), record.get("word"));
+        let inner = record.records("block").next().unwrap();
+        assert_eq!(Some(vec!["b".to_string()]), inner.get("word"));
+        let innermost = inner.records("block").next().unwrap();
+        assert_eq!(Some(vec!["c".to_string()]), innermost.get("word"));
+
+        let deep = format!("{}{}", "{".repeat(60), "}".repeat(60));
+        assert!(grammar.parse("block", deep.as_str()).is_ok());
+        let grammar = grammar.depth(10);
+        match grammar.parse("block", deep.as_str()) {
+            Err(ParseError { kind: ParsingError::TooDeep(10), ref path, .. }) if *path == vec![2, 0, 0, 0] => (),
+            other => panic!("nesting past the limit must fail, got {:?}", other),
+        }
+        let grammar = grammar.depth(DEFAULT_DEPTH_LIMIT);
+        let deeper = format!("{}{}", "{".repeat(1000), "}".repeat(1000));
+        assert!(grammar.parse("block", deeper.as_str()).is_err());
+    }
+
+    // Both alternatives parse a whole nested expr before they can fail,
+    // without a memo every level doubles the work
+    fn exprs<'c>() -> Grammar<'c, 'static> {
+        let nested = || seq(vec![tag("("), rule("expr"), tag(")")]);
+        Grammar::new().define("expr", or(vec![
+            seq(vec![nested(), tag("+")]),
+            nested(),
+            pword().chain(capture("atom")),
+        ]))
+    }
+
+    #[test]
+    fn memo_test() {
+        let input = format!("{}a{}", "(".repeat(12), ")".repeat(12));
+        let plain = exprs();
+        let (record, rest) = plain.parse_record("expr", input.as_str()).unwrap();
+        assert_eq!(Some(""), rest);
+        assert_eq!(MemoStats::default(), plain.memo_stats());
+
+        let memoized = exprs().memoize(true);
+        assert_eq!((record, rest), memoized.parse_record("expr", input.as_str()).unwrap());
+        let stats = memoized.memo_stats();
+        // the or at each of the 13 offsets and the rule at the 12 nested
+        // ones run once, the second try of each nested expr is a hit
+        assert_eq!(25, stats.misses);
+        assert_eq!(12, stats.hits);
+
+        match memoized.parse("expr", "((a)") {
+            Err(ParseError { ref path, .. }) if path.is_empty() => (),
+            other => panic!("a memoized failure keeps its path, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memo_depth_test() {
+        // `b` first matches one rule deep, then is tried again at the same
+        // offset through `w`, one rule deeper
+        let nested = || Grammar::new()
+            .define("s", or(vec![seq(vec![rule("b"), tag("!")]), rule("w")]))
+            .define("w", rule("b"))
+            .define("b", seq(vec![tag("{"), many0(rule("b")), tag("}")]));
+        let mut outcomes = vec![];
+        for limit in 1..6 {
+            let plain = nested().depth(limit).parse("s", "{{}}");
+            let memoized = nested().depth(limit).memoize(true).parse("s", "{{}}");
+            assert_eq!(format!("{:?}", plain), format!("{:?}", memoized), "depth limit {}", limit);
+            outcomes.push(plain.is_ok());
+        }
+        assert_eq!(vec![false, false, false, true, true], outcomes);
+    }
+
+    #[test]
+    fn memo_store_test() {
+        let mut words: Vec<String> = vec![];
+        {
+            let grammar = Grammar::new()
+                .define("s", or(vec![
+                    seq(vec![rule("item"), blank0(), rule("item"), tag("!")]),
+                    rule("item"),
+                ]))
+                .define("item", seq(vec![pword().chain(push(&mut words)), rule("quotes")]))
+                .define("quotes", many0(tag("'")))
+                .memoize(true);
+            assert_eq!(Some(" b"), grammar.parse("s", "a'' b").unwrap());
+            // the second alternative replays the first item, stores and all
+            assert_eq!(1, grammar.memo_stats().hits);
+            assert_eq!(Some(2), grammar.get("quotes").unwrap().iterations());
+        }
+        assert_eq!(vec!["a"], words);
+    }
+
+    #[test]
+    fn unknown_rule_test() {
+        let grammar = Grammar::new().define("list", many0(rule("item")));
+        let errors = grammar.validate().unwrap_err();
+        assert_eq!("no rule named `item` at [0]", errors[0].1.to_string());
+        assert_eq!("list", errors[0].0);
+        assert!(grammar.parse("nothing", "x").is_err());
+        assert!(rule("item").parse("x").is_err());
+    }
+}
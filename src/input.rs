@@ -0,0 +1,55 @@
+use std::fmt;
+use std::str;
+use utils::{Split, Error};
+
+/// Something parsers can run over, `&str` for text and `&[u8]` for binary
+/// formats. Offsets and lengths are always counted in bytes.
+pub trait Input<'d>: Copy + fmt::Debug {
+    fn as_bytes(&self) -> &'d [u8];
+
+    /// The input as text, None for byte inputs
+    fn as_text(&self) -> Option<&'d str>;
+
+    /// Splits after the first `count` bytes
+    fn split_at_byte(self, count: usize) -> Result<Split<Self>, Error>;
+
+    fn input_len(&self) -> usize {
+        self.as_bytes().len()
+    }
+}
+
+impl<'d> Input<'d> for &'d str {
+    fn as_bytes(&self) -> &'d [u8] {
+        str::as_bytes(self)
+    }
+
+    fn as_text(&self) -> Option<&'d str> {
+        Some(self)
+    }
+
+    fn split_at_byte(self, count: usize) -> Result<Split<Self>, Error> {
+        if self.len() < count {
+            return Err(Error::InsufficientBuffer);
+        } else if !self.is_char_boundary(count) {
+            return Err(Error::InvalidCharBoundary);
+        }
+        Ok(Split::new(&self[..count], &self[count..]))
+    }
+}
+
+impl<'d> Input<'d> for &'d [u8] {
+    fn as_bytes(&self) -> &'d [u8] {
+        self
+    }
+
+    fn as_text(&self) -> Option<&'d str> {
+        None
+    }
+
+    fn split_at_byte(self, count: usize) -> Result<Split<Self>, Error> {
+        if self.len() < count {
+            return Err(Error::InsufficientBuffer);
+        }
+        Ok(Split::new(&self[..count], &self[count..]))
+    }
+}
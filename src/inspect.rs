@@ -1,7 +1,8 @@
-use parser::{Parser, ParserType};
-use chain::{Operation, Chain};
+use std::str;
+use parser::{Parser, ParserType, BlankKind, ParseError, ParsingError};
+use chain::{Operation, Chain, StoreMode, Endian, Binary};
 
-pub fn print<'d, 'c, 't>(parser: &Parser<'d, 'c, 't>) {
+pub fn print<'c, 't>(parser: &Parser<'c, 't>) {
     print_aux(parser, Indent::new())
 }
 
@@ -10,7 +11,6 @@ enum Indentation {
     Space(usize),
     BlockEnd(usize),
     Block(usize),
-    Word(String),
 }
 
 #[derive(Clone)]
@@ -26,19 +26,16 @@ impl Indent {
     fn push_clone(&self, indent: Indentation) -> Indent {
         let mut ni = Indent::new();
         for i in self.indents.iter() {
-            match i {
-                &Indentation::BlockEnd(spaces) => {
+            match *i {
+                Indentation::BlockEnd(spaces) => {
                     if let Indentation::BlockEnd(_) = indent {
                         ni.indents.push(Indentation::Block(spaces));
                     } else {
                         ni.indents.push(Indentation::BlockEnd(spaces));
                     }
                 }
-                &Indentation::Space(spaces) => ni.indents.push(Indentation::Space(spaces)),
-                &Indentation::Block(spaces) => ni.indents.push(Indentation::Block(spaces)),
-                &Indentation::Word(ref string) => {
-                    ni.indents.push(Indentation::Word(string.clone()))
-                }
+                Indentation::Space(spaces) => ni.indents.push(Indentation::Space(spaces)),
+                Indentation::Block(spaces) => ni.indents.push(Indentation::Block(spaces)),
             }
         }
         ni.indents.push(indent);
@@ -47,20 +44,19 @@ impl Indent {
 
     fn print(&self) {
         for i in self.indents.iter() {
-            match i {
-                &Indentation::Space(c) => {
+            match *i {
+                Indentation::Space(c) => {
                     Indent::print_spaces(c);
                 }
-                &Indentation::BlockEnd(c) => {
+                Indentation::BlockEnd(c) => {
                     Indent::print_spaces(c);
                     print!(" |- ");
 
                 }
-                &Indentation::Block(c) => {
+                Indentation::Block(c) => {
                     Indent::print_spaces(c);
                     print!(" |  ");
                 }
-                &Indentation::Word(ref s) => print!("'{}'", s),
             }
         }
     }
@@ -72,17 +68,41 @@ impl Indent {
     }
 }
 
-fn print_aux<'d, 'c, 't>(parser: &Parser<'d, 'c, 't>, indent: Indent) {
+fn print_aux<'c, 't>(parser: &Parser<'c, 't>, indent: Indent) {
     match parser.ptype {
         ParserType::Sequence(ref list) => {
-            print!("seq -|- ");
-            let new_ident = indent.push_clone(Indentation::BlockEnd(4));
-            for i in 0..list.len() {
-                if i > 0 {
-                    new_ident.print();
-                }
-                print_aux(&list[i], new_ident.clone());
-            }
+            print_list("seq", list.iter().collect(), indent);
+        }
+        ParserType::Or(ref list) => {
+            print_list("or", list.iter().collect(), indent);
+        }
+        ParserType::Many0(ref p) => {
+            print_list("many0", vec![p], indent);
+        }
+        ParserType::Many1(ref p) => {
+            print_list("many1", vec![p], indent);
+        }
+        ParserType::Count(times, ref p) => {
+            print_list(&format!("count {}", times), vec![p], indent);
+        }
+        ParserType::SepBy(ref p, ref sep) => {
+            print_list("sep_by", vec![p, sep], indent);
+        }
+        ParserType::Opt(ref p) => {
+            print_list("opt", vec![p], indent);
+        }
+        ParserType::Peek(ref p) => {
+            print_list("peek", vec![p], indent);
+        }
+        ParserType::Not(ref p) => {
+            print_list("not", vec![p], indent);
+        }
+        ParserType::Precedence(_) => {
+            print_list("precedence", parser.children(), indent);
+        }
+        // Shows its chain, the only list parser that can have one
+        ParserType::Delimited(_, _, _) => {
+            print_list(&format_node(parser), parser.children(), indent);
         }
         ParserType::Take(count) => {
             let ftake = format!("take {}", count);
@@ -97,28 +117,169 @@ fn print_aux<'d, 'c, 't>(parser: &Parser<'d, 'c, 't>, indent: Indent) {
         ParserType::Skip(count) => {
             let fskip = format!("skip {}", count);
             print!("{}", fskip);
-            let new_ident = indent.push_clone(Indentation::Space(fskip.len()));
             println!();
         }
-         ParserType::Tag(word) => {
-            let ftag = format!("tag {}", word);
+        ParserType::Tag(_) => {
+            let ftag = node_name(parser);
             print!("{}", ftag);
-            let new_ident = indent.push_clone(Indentation::Space(ftag.len()));
             println!();
         }
-        ParserType::PWord => {
-            let fpword = "pword";
-            print!("{}", fpword);
-            let new_ident = indent.push_clone(Indentation::Space(fpword.len()));
+        ParserType::Rule(_) => {
+            let frule = node_name(parser);
+            print!("{}", frule);
+            println!();
+        }
+        ParserType::PWord | ParserType::Blank(_) | ParserType::Until(_, _) | ParserType::Balanced(_, _) => {
+            let fname = node_name(parser);
+            print!("{}", fname);
+            let new_ident = indent.push_clone(Indentation::Space(fname.len()));
+            if let Some(ref c) = parser.chain {
+                print_chain(c, new_ident, false, false);
+            } else {
+                println!();
+            }
         }
-        ParserType::Blank => {
-            let fblank = "blank";
-            print!("{}", fblank);
-            let new_ident = indent.push_clone(Indentation::Space(fblank.len()));
+    }
+}
+
+fn print_list<'c, 't>(name: &str, list: Vec<&Parser<'c, 't>>, indent: Indent) {
+    print!("{} -|- ", name);
+    let new_ident = indent.push_clone(Indentation::BlockEnd(name.len() + 1));
+    for (i, p) in list.iter().enumerate() {
+        if i > 0 {
+            new_ident.print();
         }
+        print_aux(p, new_ident.clone());
     }
 }
 
+/// Formats a single parser on one line, without its children.
+/// A take with a chain looks like `take 8 -> trim -> to_f64 -> store`.
+pub fn format_node<'c, 't>(parser: &Parser<'c, 't>) -> String {
+    let mut formated = node_name(parser);
+    if let Some(ref name) = parser.name {
+        formated.push_str(&format!(" as {}", name));
+    }
+    if let Some(ref c) = parser.chain {
+        formated.push_str(" -> ");
+        formated.push_str(&format_chain(c));
+    }
+    formated
+}
+
+fn node_name<'c, 't>(parser: &Parser<'c, 't>) -> String {
+    match parser.ptype {
+        ParserType::Sequence(_) => String::from("seq"),
+        ParserType::Or(_) => String::from("or"),
+        ParserType::Many0(_) => String::from("many0"),
+        ParserType::Many1(_) => String::from("many1"),
+        ParserType::Count(times, _) => format!("count {}", times),
+        ParserType::SepBy(_, _) => String::from("sep_by"),
+        ParserType::Opt(_) => String::from("opt"),
+        ParserType::Peek(_) => String::from("peek"),
+        ParserType::Not(_) => String::from("not"),
+        ParserType::Delimited(Some(_), _, Some(_)) => String::from("delimited"),
+        ParserType::Delimited(Some(_), _, None) => String::from("preceded"),
+        ParserType::Delimited(None, _, _) => String::from("terminated"),
+        ParserType::Balanced(open, close) => format!("balanced {} {}", open, close),
+        ParserType::Rule(name) => format!("rule {}", name),
+        ParserType::Precedence(_) => String::from("precedence"),
+        ParserType::Take(count) => format!("take {}", count),
+        ParserType::Skip(count) => format!("skip {}", count),
+        ParserType::Tag(word) => match str::from_utf8(word) {
+            Ok(text) => format!("tag {}", text),
+            Err(_) => format!("tag {:?}", word),
+        },
+        ParserType::PWord => String::from("pword"),
+        ParserType::Until(ref terminators, ref escapes) if escapes.is_empty() => {
+            format!("until {:?}", terminators)
+        }
+        ParserType::Until(ref terminators, ref escapes) => {
+            format!("until {:?} escape {:?}", terminators, escapes)
+        }
+        ParserType::Blank(BlankKind::Blank0) => String::from("blank0"),
+        ParserType::Blank(BlankKind::Blank1) => String::from("blank1"),
+        ParserType::Blank(BlankKind::Space0) => String::from("space0"),
+        ParserType::Blank(BlankKind::Space1) => String::from("space1"),
+        ParserType::Blank(BlankKind::LineEnding) => String::from("line_ending"),
+    }
+}
+
+fn format_chain(chain: &Chain) -> String {
+    let mut ops = vec![];
+    for op in chain.olist.iter() {
+        match *op {
+            Operation::Or(ref chains) => {
+                let alternatives: Vec<String> = chains.iter().map(format_chain).collect();
+                ops.push(format!("or [{}]", alternatives.join(" | ")));
+            }
+            Operation::Opt(ref c) => ops.push(format!("opt <{}>", format_chain(c))),
+            _ => ops.push(format_op(op)),
+        }
+    }
+    ops.join(" -> ")
+}
+
+/// Renders a parse error with the line of `input` it happened in and a caret
+/// under the failing span. `parser` and `input` must be the ones that produced
+/// the error.
+///
+/// ```text
+/// error: not a valid f64
+///   --> line 1, column 31 in `take 8 -> trim -> to_f64 -> store`
+///   |
+/// 1 | ATOM     23  CG1 VAL R   3      58.8x6  39.331  40.265
+///   |                               ^^^^^^^^
+///   = found `  58.8x6`
+/// ```
+pub fn render_error<'c, 't>(error: &ParseError, input: &str, parser: &Parser<'c, 't>) -> String {
+    let span = &error.span;
+    let node = match parser.node(&error.path) {
+        Some(p) => format_node(p),
+        None => String::from("?"),
+    };
+    // Spans count bytes, a byte parser may point inside a char
+    let mut offset = span.offset.min(input.len());
+    while !input.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let line_start = match input[..offset].rfind('\n') {
+        Some(i) => i + 1,
+        None => 0,
+    };
+    let line_end = match input[offset..].find('\n') {
+        Some(i) => offset + i,
+        None => input.len(),
+    };
+    let line = input[line_start..line_end].trim_end_matches('\r');
+    // The found text takes every char the span touches
+    let end = match input[offset..line_end].char_indices().find(|&(i, _)| offset + i >= span.offset + span.len) {
+        Some((i, _)) => offset + i,
+        None => line_end,
+    };
+    let found = &input[offset..end];
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+
+    let mut rendered = format!("error: {}\n", error.kind);
+    rendered.push_str(&format!("{} --> line {}, column {} in `{}`\n", gutter, span.line, span.column, node));
+    rendered.push_str(&format!("{} |\n", gutter));
+    rendered.push_str(&format!("{} | {}\n", number, line));
+    rendered.push_str(&format!("{} | {}{}\n",
+                               gutter,
+                               " ".repeat(span.column.saturating_sub(1)),
+                               "^".repeat(found.chars().count().max(1))));
+    if !found.is_empty() {
+        rendered.push_str(&format!("{} = found `{}`\n", gutter, found));
+    }
+    if let ParsingError::NoAlternative(ref errors) = error.kind {
+        for (i, e) in errors.iter().enumerate() {
+            rendered.push_str(&format!("{} = alternative {}: {}\n", gutter, i + 1, e));
+        }
+    }
+    rendered
+}
+
 fn format_op(op: &Operation) -> String {
     let mut formated = String::new();
     match *op {
@@ -127,13 +288,36 @@ fn format_op(op: &Operation) -> String {
         Operation::EqualString(ref s) => formated.push_str(format!("eq {}", s).as_str()),
         Operation::NotEqualString(ref s) => formated.push_str(format!("neq {}", s).as_str()),
         Operation::Trim => formated.push_str("trim"),
-        Operation::Store(_) => formated.push_str("store"),
+        Operation::Store(StoreMode::Replace, _) => formated.push_str("store"),
+        Operation::Store(StoreMode::Append, _) => formated.push_str("append"),
+        Operation::Store(StoreMode::Push, _) => formated.push_str("push"),
+        Operation::StoreAt(StoreMode::Replace, _) => formated.push_str("store_at"),
+        Operation::StoreAt(StoreMode::Append, _) => formated.push_str("append_at"),
+        Operation::StoreAt(StoreMode::Push, _) => formated.push_str("push_at"),
+        Operation::Capture(ref name) => formated.push_str(&format!("capture {}", name)),
         Operation::Empty => formated.push_str("empty"),
         Operation::Or(_) => formated.push_str("or -|- "),
         Operation::ToI32 => formated.push_str("to_i32"),
         Operation::ToF64 => formated.push_str("to_f64"),
         Operation::ToU8 => formated.push_str("to_u8"),
         Operation::ToByte => formated.push_str("to_byte"),
+        Operation::Bytes => formated.push_str("bytes"),
+        Operation::Decode(endian, binary) => {
+            formated.push_str(match endian {
+                Endian::Little => "le_",
+                Endian::Big => "be_",
+            });
+            formated.push_str(match binary {
+                Binary::U16 => "u16",
+                Binary::I16 => "i16",
+                Binary::U32 => "u32",
+                Binary::I32 => "i32",
+                Binary::U64 => "u64",
+                Binary::I64 => "i64",
+                Binary::F32 => "f32",
+                Binary::F64 => "f64",
+            });
+        }
         Operation::Opt(_) => formated.push_str("opt <"),
     }
     formated
@@ -157,12 +341,12 @@ fn print_chain<'a>(chain: &Chain<'a>, indent: Indent, chain_arrow: bool, opt : b
         if let Operation::Or(ref chains) = chain.olist[i] {
             sub = true;
             ni = ni.push_clone(Indentation::BlockEnd(3));
-            for i in 0..chains.len() {
+            for (i, c) in chains.iter().enumerate() {
                 if i == 0 {
-                    print_chain(&chains[i], ni.clone(), true, false);
+                    print_chain(c, ni.clone(), true, false);
                 } else {
                     ni.print();
-                    print_chain(&chains[i], ni.clone(), true, false);
+                    print_chain(c, ni.clone(), true, false);
                 }
             }
         } else if let Operation::Opt(ref chain) = chain.olist[i] {
@@ -181,4 +365,51 @@ fn print_chain<'a>(chain: &Chain<'a>, indent: Indent, chain_arrow: bool, opt : b
             println!();
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::{seq, take, skip, tag};
+    use chain::{trim, trimr};
+
+    #[test]
+    fn render_error_test() {
+        let input = "ATOM     23  CG1 VAL R   3      58.8x6  39.331  40.265";
+        let parser = seq(vec![
+            take(6).chain(trimr().eq_str("ATOM")),
+            skip(24),
+            take(8).chain(trim().to_f64()),
+        ]);
+        let error = parser.parse(input).unwrap_err();
+        assert_eq!(
+"error: not a valid f64
+  --> line 1, column 31 in `take 8 -> trim -> to_f64`
+  |
+1 | ATOM     23  CG1 VAL R   3      58.8x6  39.331  40.265
+  |                               ^^^^^^^^
+  = found `  58.8x6`
+", render_error(&error, input, &parser));
+    }
+
+    #[test]
+    fn render_error_non_ascii_test() {
+        let input = "aéx";
+        let parser = seq(vec![tag("a"), tag("b")]);
+        let error = parser.parse(input).unwrap_err();
+        assert_eq!((1, 1), (error.span.offset, error.span.len));
+        assert_eq!(
+"error: not on a utf8 char boundary
+  --> line 1, column 2 in `tag b`
+  |
+1 | aéx
+  |  ^
+  = found `é`
+", render_error(&error, input, &parser));
+
+        // an error that was never located has no column
+        let mut unlocated = error.clone();
+        unlocated.span.column = 0;
+        assert!(render_error(&unlocated, input, &parser).contains("1 | aéx\n  | ^\n"));
+    }
+}
@@ -0,0 +1,22 @@
+extern crate super_parser_derive;
+#[cfg(feature = "serde")]
+extern crate serde;
+// Lets code derived inside this crate name it like users do
+extern crate self as super_parser;
+
+pub mod chain;
+pub mod parser;
+pub mod utils;
+pub mod input;
+pub mod stream;
+pub mod pdb_parser;
+pub mod dml_parser;
+pub mod inspect;
+pub mod value;
+pub mod record;
+pub mod fixed;
+pub mod dsl;
+pub mod grammar;
+pub mod precedence;
+#[cfg(feature = "serde")]
+pub mod de;
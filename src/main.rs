@@ -1,12 +1,10 @@
-mod chain;
-mod parser;
-mod utils;
-mod pdb_parser;
-mod inspect;
-use utils::{until, pword, tag};
-use parser::{seq, take, skip};
-use pdb_parser::ATOM;
-use chain::{trimr, triml, trim, empty, to_i32, to_u8, to_byte, to_f64, opt, store};
+extern crate super_parser;
+
+use super_parser::utils;
+use super_parser::utils::{until, pword, tag};
+use super_parser::pdb_parser::ATOM;
+use super_parser::dml_parser::DefUse;
+use super_parser::parser;
 
 fn parse_pdb() {
     //let test1 = "ATOM     89  OG  SER A 693      25.623   8.222  86.526  1.00 43.01           O ";
@@ -14,12 +12,15 @@ fn parse_pdb() {
     //let test3 = "ATOM     90 2HE2 GLN R   7      55.573  32.236  40.457  1.00  0.00";
     let test4 = "ATOM     23  CG1 VAL R   3      58.836  39.331  40.265  1.00  0.00";
     let mut atom = ATOM::new();
-    atom.parse(&test4);    
+    match atom.parse(test4) {
+        Ok(()) => println!("Parser succeeded!"),
+        Err(e) => println!("{}", e),
+    }
     println!("Serial {}", atom.serial);
     println!("Name {}", atom.name);
-    println!("Alt Loc {}", atom.alt_loc);
+    println!("Alt Loc {:?}", atom.alt_loc);
     println!("Res Name {}", atom.res_name);
-    println!("ICode {}", atom.icode as char);
+    println!("ICode {:?}", atom.icode);
     println!("X {}", atom.x);
     println!("Y {}", atom.y);
     println!("Z {}", atom.z);
@@ -46,6 +47,12 @@ def block main {{
 use core
 main {}";
 
+    let mut def_use = DefUse::default();
+    let parser = parser::preceded(parser::tag("def use "), DefUse::parser());
+    match parser.parse_into(dml, &mut def_use) {
+        Ok(_) => println!("Use {}:\n{}", def_use.name, def_use.data),
+        Err(e) => println!("{}", e),
+    }
 }
 
 fn main() {
@@ -56,10 +63,12 @@ fn main() {
     println!("trim: {:?}", utils::trim(test_trim));
     println!("------------ Test PDB Parser ------------");
     parse_pdb();
+    println!("------------ Test DarXML Parser ------------");
+    parse_darxml();
     println!("------------ Test Until ------------");
-    println!("{:?}", until(&"abcdefg", &[&"j", &"w"]));
+    println!("{:?}", until("abcdefg", &["j", "w"]));
     println!("------------ Test PWord ------------");
-    println!("word {:?}", pword(&"d_7_ te2_243 st test"));
+    println!("word {:?}", pword("d_7_ te2_243 st test"));
     println!("------------ Test Tag ------------");
-    println!("tag {:?}", tag(&"d_7_ te2_243 st test", "d_7_ te2_243 st test"));
+    println!("tag {:?}", tag("d_7_ te2_243 st test", "d_7_ te2_243 st test"));
 }
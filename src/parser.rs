@@ -1,71 +1,385 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::str;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::vec::Vec;
-use chain::{Chain, Operation};
+use chain::{Chain, Operation, StoreMode, Endian, Binary, Accessor};
+use value::{Value, Field};
+use record::Record;
 use utils;
+use grammar::{Grammar, MemoStats};
+use input::Input;
+use precedence::{Expression, Operators, Part, Climbed, Build, Assoc};
 
-pub enum ParserType<'d, 'a, 't> {
+pub enum ParserType<'c, 't> {
     /// Stores a Sequence of parsers. Executes until all pass or one failure occurs.
-    Sequence(Vec<Parser<'d, 'a, 't>>),
+    Sequence(Vec<Parser<'c, 't>>),
+    /// Stores alternative parsers. Each one is tried against the same input,
+    /// the first that passes wins.
+    Or(Vec<Parser<'c, 't>>),
+    /// Applies the parser until it fails. Zero matches is a success.
+    Many0(Box<Parser<'c, 't>>),
+    /// Applies the parser until it fails. At least one match is required.
+    Many1(Box<Parser<'c, 't>>),
+    /// Applies the parser exactly n times.
+    Count(usize, Box<Parser<'c, 't>>),
+    /// Applies the first parser zero or more times, with the second parser
+    /// matched between each application.
+    SepBy(Box<Parser<'c, 't>>, Box<Parser<'c, 't>>),
+    /// Applies the parser, if it fails nothing is consumed and the
+    /// parse goes on.
+    Opt(Box<Parser<'c, 't>>),
+    /// Succeeds if the parser matches, without consuming anything.
+    Peek(Box<Parser<'c, 't>>),
+    /// Succeeds if the parser doesn't match, without consuming anything.
+    Not(Box<Parser<'c, 't>>),
+    /// Applies the parsers in order, a chain only gets what the middle
+    /// one matched: (open, inner, close). Preceded and terminated leave
+    /// out a side.
+    Delimited(Option<Box<Parser<'c, 't>>>, Box<Parser<'c, 't>>, Option<Box<Parser<'c, 't>>>),
+    /// Takes from the open delimiter to the close that matches it, nested
+    /// pairs included. A chain gets the text between them: (open, close)
+    Balanced(&'t str, &'t str),
+    /// The parser of a `Grammar` rule, found by name when it runs so rules
+    /// can refer to themselves.
+    Rule(&'t str),
     Take(usize),
     Skip(usize),
     PWord,
-    Blank,
-    Tag(&'t str)
+    Blank(BlankKind),
+    Tag(&'t [u8]),
+    /// Takes everything up to, not including, the first terminator or the
+    /// end of the input. Escapes are skipped whole so a terminator inside
+    /// one doesn't stop it: (terminators, escapes)
+    Until(Vec<&'t str>, Vec<&'t str>),
+    /// Operands and operators climbed by precedence, made by
+    /// `Precedence::parser`. Its children are its parsers.
+    Precedence(Box<Expression<'c, 't>>),
 }
 
-pub struct Parser<'d, 'c, 't> {
-    pub ptype: ParserType<'d, 'c, 't>,
+/// What a blank parser accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlankKind {
+    /// Zero or more spaces, tabs, `\r` or `\n`
+    Blank0,
+    /// One or more spaces, tabs, `\r` or `\n`
+    Blank1,
+    /// Zero or more spaces or tabs
+    Space0,
+    /// One or more spaces or tabs
+    Space1,
+    /// A single `\n` or `\r\n`
+    LineEnding,
+}
+
+pub struct Parser<'c, 't> {
+    pub ptype: ParserType<'c, 't>,
     pub chain: Option<Chain<'c>>,
-    parsed: Option<&'d str>,
+    /// Captures made inside go into a record under this name
+    pub name: Option<String>,
+    iterations: Cell<Option<usize>>,
+    // For repetitions, the names their captures are collected under
+    lists: Vec<String>,
 }
 
-impl<'d, 'c, 't> Parser<'d, 'c, 't> {
-    fn new(ptype: ParserType<'d, 'c, 't>) -> Parser<'d, 'c, 't> {
+impl<'c, 't> Parser<'c, 't> {
+    fn new(ptype: ParserType<'c, 't>) -> Parser<'c, 't> {
         Parser {
-            ptype: ptype,
+            ptype,
             chain: None,
-            parsed: None,
+            name: None,
+            iterations: Cell::new(None),
+            lists: vec![],
+        }
+    }
+
+    // The children are complete once they are repeated, so the lists are
+    // known from the start
+    fn repetition(ptype: ParserType<'c, 't>) -> Parser<'c, 't> {
+        let mut parser = Parser::new(ptype);
+        let mut lists = vec![];
+        for child in parser.children() {
+            list_names(child, &mut lists);
         }
+        parser.lists = lists;
+        parser
+    }
+
+    /// Runs the parser over `buffer`, text or bytes. Stores are only written once the
+    /// whole parser has succeeded, a failed parse leaves every target as it was.
+    pub fn parse<'d, I: Input<'d>>(&self, buffer: I) -> Result<Option<I>, ParseError> {
+        self.parse_with(buffer, false, None, None).map(|(rest, _)| rest)
+    }
+
+    /// Same as parse, but `buffer` is taken to be only the start of the
+    /// input. A parser that runs out of data, or can't tell if it has
+    /// finished, fails with `Incomplete(needed)` and nothing is stored,
+    /// so it can be run again once more data is in `buffer`.
+    pub fn parse_partial<'d, I: Input<'d>>(&self, buffer: I) -> Result<Option<I>, ParseError> {
+        self.parse_with(buffer, true, None, None).map(|(rest, _)| rest)
+    }
+
+    /// Same as parse, with `output` as the target of the `store_at` family.
+    /// The parser doesn't borrow `output`, so it can be built once and
+    /// run with a new output each time.
+    pub fn parse_into<'d, I: Input<'d>, T: Any>(&self, buffer: I, output: &mut T) -> Result<Option<I>, ParseError> {
+        self.parse_with(buffer, false, Some(output), None).map(|(rest, _)| rest)
     }
 
-    pub fn parse(&mut self, buffer: &'d str) -> Result<Option<&'d str>, ParsingError> {
-        run_parser(self, buffer)
+    /// Same as parse, also returns what the `capture` operations captured.
+    /// Only named parsers nest a record, captures repeated by a repetition
+    /// are always a list, empty when it matched nothing.
+    pub fn parse_record<'d, I: Input<'d>>(&self, buffer: I) -> Result<(Record, Option<I>), ParseError> {
+        self.parse_with(buffer, false, None, None).map(|(rest, record)| (record, rest))
     }
 
+    pub(crate) fn parse_with<'d, I: Input<'d>>(&self,
+                                               buffer: I,
+                                               partial: bool,
+                                               output: Option<&mut dyn Any>,
+                                               grammar: Option<&Grammar<'c, 't>>)
+                                               -> Result<(Option<I>, Record), ParseError> {
+        let mut run = Run::new(buffer.input_len(), partial, output, grammar);
+        let result = run_parser(self, buffer, &mut run);
+        if let (Some(g), Some(ref memo)) = (grammar, &run.memo) {
+            g.set_memo_stats(memo.stats);
+        }
+        match result {
+            Ok(rest) => {
+                let record = run.tx.commit();
+                Ok((Some(rest), record))
+            }
+            Err(mut pe) => {
+                pe.locate(buffer.as_bytes());
+                Err(pe)
+            }
+        }
+    }
 
     pub fn chain(mut self, c: Chain<'c>) -> Self {
         self.chain = Some(c);
         self
     }
+
+    /// Captures made inside this parser go into a record of their own,
+    /// under `name`. A named repetition makes a list of records, one per
+    /// match. Without a name, nested sequences keep no record of their own.
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Walks the whole tree once, without running it, and reports every
+    /// structural problem found along with the path of its parser.
+    /// Parsing doesn't check the structure again, validate a parser once
+    /// when it is built.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        validate_aux(self, &mut vec![], &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parsers directly under this one, in the order a path indexes them
+    pub fn children(&self) -> Vec<&Parser<'c, 't>> {
+        match self.ptype {
+            ParserType::Sequence(ref list) | ParserType::Or(ref list) => list.iter().collect(),
+            ParserType::Many0(ref p) |
+            ParserType::Many1(ref p) |
+            ParserType::Count(_, ref p) |
+            ParserType::Opt(ref p) |
+            ParserType::Peek(ref p) |
+            ParserType::Not(ref p) => vec![p],
+            ParserType::SepBy(ref p, ref sep) => vec![p, sep],
+            ParserType::Delimited(ref open, ref p, ref close) => {
+                open.as_deref().into_iter().chain(Some(&**p)).chain(close.as_deref()).collect()
+            }
+            ParserType::Precedence(ref expression) => expression.operators.children(),
+            _ => vec![],
+        }
+    }
+
+    /// Follows a path such as `ParseError::path` down the tree
+    pub fn node(&self, path: &[usize]) -> Option<&Parser<'c, 't>> {
+        match path.split_first() {
+            None => Some(self),
+            Some((&i, rest)) => match self.children().get(i) {
+                Some(child) => child.node(rest),
+                None => None,
+            },
+        }
+    }
+
+    /// How many times a repetition parser matched in its last run.
+    /// None for parsers that don't repeat or that haven't run yet.
+    pub fn iterations(&self) -> Option<usize> {
+        self.iterations.get()
+    }
 }
 
-pub fn seq<'d, 'c, 't>(list_parsers: Vec<Parser<'d, 'c, 't>>) -> Parser<'d, 'c, 't> {
+pub fn seq<'c, 't>(list_parsers: Vec<Parser<'c, 't>>) -> Parser<'c, 't> {
     Parser::new(ParserType::Sequence(list_parsers))
 }
 
-pub fn take<'d, 'c, 't>(count: usize) -> Parser<'d, 'c, 't> {
+pub fn or<'c, 't>(list_parsers: Vec<Parser<'c, 't>>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Or(list_parsers))
+}
+
+pub fn many0<'c, 't>(parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::repetition(ParserType::Many0(Box::new(parser)))
+}
+
+pub fn many1<'c, 't>(parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::repetition(ParserType::Many1(Box::new(parser)))
+}
+
+pub fn count<'c, 't>(times: usize, parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::repetition(ParserType::Count(times, Box::new(parser)))
+}
+
+pub fn sep_by<'c, 't>(parser: Parser<'c, 't>, separator: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::repetition(ParserType::SepBy(Box::new(parser), Box::new(separator)))
+}
+
+pub(crate) fn expression<'c, 't>(expression: Expression<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Precedence(Box::new(expression)))
+}
+
+/// `opt(tag(","))` matches a comma if there is one
+pub fn opt<'c, 't>(parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Opt(Box::new(parser)))
+}
+
+/// Looks ahead, `seq(vec![peek(tag("def")), pword()])` takes a word
+/// only if it starts with "def"
+pub fn peek<'c, 't>(parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Peek(Box::new(parser)))
+}
+
+/// Looks ahead, `seq(vec![not(tag("def")), pword()])` takes any word
+/// that doesn't start with "def"
+pub fn not<'c, 't>(parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Not(Box::new(parser)))
+}
+
+/// `delimited(tag("("), pword(), tag(")"))`, a chain on it gets the word
+pub fn delimited<'c, 't>(open: Parser<'c, 't>, parser: Parser<'c, 't>, close: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Delimited(Some(Box::new(open)), Box::new(parser), Some(Box::new(close))))
+}
+
+pub fn preceded<'c, 't>(open: Parser<'c, 't>, parser: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Delimited(Some(Box::new(open)), Box::new(parser), None))
+}
+
+pub fn terminated<'c, 't>(parser: Parser<'c, 't>, close: Parser<'c, 't>) -> Parser<'c, 't> {
+    Parser::new(ParserType::Delimited(None, Box::new(parser), Some(Box::new(close))))
+}
+
+/// `balanced("{{", "}}")` takes a whole `{{ a {{ b }} }}` block
+pub fn balanced<'c, 't>(open: &'t str, close: &'t str) -> Parser<'c, 't> {
+    Parser::new(ParserType::Balanced(open, close))
+}
+
+/// The rule `name` of the grammar the parse runs with, see `Grammar`
+pub fn rule<'c, 't>(name: &'t str) -> Parser<'c, 't> {
+    Parser::new(ParserType::Rule(name))
+}
+
+pub fn take<'c, 't>(count: usize) -> Parser<'c, 't> {
     Parser::new(ParserType::Take(count))
 }
 
-pub fn skip<'d, 'c, 't>(count: usize) -> Parser<'d, 'c, 't> {
+pub fn skip<'c, 't>(count: usize) -> Parser<'c, 't> {
     Parser::new(ParserType::Skip(count))
 }
 
-pub fn pword<'d, 'c, 't>() -> Parser<'d, 'c, 't> {
+pub fn pword<'c, 't>() -> Parser<'c, 't> {
     Parser::new(ParserType::PWord)
 }
 
-pub fn blank<'d, 'c, 't>() -> Parser<'d, 'c, 't> {
-    Parser::new(ParserType::Blank)
+/// Optional whitespace, same as blank0
+pub fn blank<'c, 't>() -> Parser<'c, 't> {
+    blank0()
 }
 
-#[derive(Debug)]
-enum InvalidParserError {
+pub fn blank0<'c, 't>() -> Parser<'c, 't> {
+    Parser::new(ParserType::Blank(BlankKind::Blank0))
+}
+
+pub fn blank1<'c, 't>() -> Parser<'c, 't> {
+    Parser::new(ParserType::Blank(BlankKind::Blank1))
+}
+
+/// Optional whitespace that doesn't cross lines
+pub fn space0<'c, 't>() -> Parser<'c, 't> {
+    Parser::new(ParserType::Blank(BlankKind::Space0))
+}
+
+pub fn space1<'c, 't>() -> Parser<'c, 't> {
+    Parser::new(ParserType::Blank(BlankKind::Space1))
+}
+
+pub fn line_ending<'c, 't>() -> Parser<'c, 't> {
+    Parser::new(ParserType::Blank(BlankKind::LineEnding))
+}
+
+/// Matches `value` exactly, `tag("ATOM")` or `tag(b"\x7fELF")`
+pub fn tag<'c, 't, T: AsRef<[u8]> + ?Sized>(value: &'t T) -> Parser<'c, 't> {
+    Parser::new(ParserType::Tag(value.as_ref()))
+}
+
+/// `until(&["}}"], &["\\}}"])` takes up to the first `}}` not part of a `\}}`
+pub fn until<'c, 't>(terminators: &[&'t str], escapes: &[&'t str]) -> Parser<'c, 't> {
+    Parser::new(ParserType::Until(terminators.to_vec(), escapes.to_vec()))
+}
+
+#[derive(Debug, Clone)]
+pub enum InvalidParserError {
     // Sequence has no parsers
     SeqEmpty,
     // Not a sequence
     SeqNot,
+    // Not an or
+    OrNot,
+    // Not a repetition (many0, many1, count, sep_by)
+    RepeatNot,
+    // Not an opt, peek or not
+    LookNot,
+    // Not a delimited, preceded or terminated
+    DelimitedNot,
+    // Not a balanced
+    BalancedNot,
+    // Balanced needs non empty delimiters
+    BalancedEmpty,
+    // Not a rule
+    RuleNot,
+    // Not a precedence
+    PrecedenceNot,
+    // Precedence has no atoms
+    PrecedenceEmpty,
+    // Precedence cannot have chain
+    PrecedenceChain,
+    // Rule cannot have a chain
+    RuleChain,
+    // The grammar has no rule with this name
+    UnknownRule(String),
     // Sequence cannot have chain
     SeqChain,
+    // Or has no parsers
+    OrEmpty,
+    // Or cannot have chain
+    OrChain,
+    // Repetitions cannot have chain
+    RepeatChain,
+    // Opt, peek and not cannot have a chain
+    LookChain,
+    // Skip cannot have chain
+    SkipChain,
     // Not a take
     TakeNot,
     // Not a skip
@@ -74,17 +388,78 @@ enum InvalidParserError {
     BlankNot,
     // Not a pword
     PWordNot,
+    // Not an until
+    UntilNot,
+    // Until needs at least one terminator and none can be empty
+    UntilTerminator,
     // Must take mode then zero bytes
     TakeZero,
     // Or must be last in operation chain
     ChainOrLast,
     // Store must be last in operation chain
     ChainStoreLast,
+    // Nothing can follow opt in a chain, not even a store
+    ChainAfterOpt,
+    // Operation expected a value of the first type but the chain
+    // gives it the second
+    ChainType(ParsingDataTypes, ParsingDataTypes),
+    // Store target cannot hold the type the chain gives it
+    StoreType(ParsingDataTypes),
+    // The alternative of a chain or at this index gives the second type,
+    // the first alternative gives the first
+    ChainOrType(usize, ParsingDataTypes, ParsingDataTypes),
     // Unexpected error in blank parser
     BlankError,
 }
 
-#[derive(Debug)]
+impl fmt::Display for InvalidParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidParserError::SeqEmpty => write!(f, "sequence has no parsers"),
+            InvalidParserError::SeqNot => write!(f, "not a sequence"),
+            InvalidParserError::OrNot => write!(f, "not an or"),
+            InvalidParserError::RepeatNot => write!(f, "not a repetition"),
+            InvalidParserError::LookNot => write!(f, "not an opt, peek or not"),
+            InvalidParserError::DelimitedNot => write!(f, "not a delimited"),
+            InvalidParserError::BalancedNot => write!(f, "not a balanced"),
+            InvalidParserError::BalancedEmpty => write!(f, "balanced needs non empty delimiters"),
+            InvalidParserError::RuleNot => write!(f, "not a rule"),
+            InvalidParserError::PrecedenceNot => write!(f, "not a precedence"),
+            InvalidParserError::PrecedenceEmpty => write!(f, "precedence has no atoms"),
+            InvalidParserError::PrecedenceChain => write!(f, "precedence cannot have a chain"),
+            InvalidParserError::RuleChain => write!(f, "rule cannot have a chain"),
+            InvalidParserError::UnknownRule(ref name) => write!(f, "no rule named `{}`", name),
+            InvalidParserError::SeqChain => write!(f, "sequence cannot have a chain"),
+            InvalidParserError::OrEmpty => write!(f, "or has no parsers"),
+            InvalidParserError::OrChain => write!(f, "or cannot have a chain"),
+            InvalidParserError::RepeatChain => write!(f, "repetition cannot have a chain"),
+            InvalidParserError::LookChain => write!(f, "opt, peek and not cannot have a chain"),
+            InvalidParserError::SkipChain => write!(f, "skip cannot have a chain"),
+            InvalidParserError::TakeNot => write!(f, "not a take"),
+            InvalidParserError::SkipNot => write!(f, "not a skip"),
+            InvalidParserError::BlankNot => write!(f, "not a blank"),
+            InvalidParserError::PWordNot => write!(f, "not a pword"),
+            InvalidParserError::UntilNot => write!(f, "not an until"),
+            InvalidParserError::UntilTerminator => write!(f, "until needs non empty terminators"),
+            InvalidParserError::TakeZero => write!(f, "take must be of at least one byte"),
+            InvalidParserError::ChainOrLast => write!(f, "or must be the last operation of a chain"),
+            InvalidParserError::ChainStoreLast => write!(f, "store must be the last operation of a chain"),
+            InvalidParserError::ChainAfterOpt => write!(f, "nothing can follow opt in a chain"),
+            InvalidParserError::ChainType(expected, found) => {
+                write!(f, "chain operation expected {}, found {}", expected, found)
+            }
+            InvalidParserError::StoreType(found) => {
+                write!(f, "store target cannot hold a {}", found)
+            }
+            InvalidParserError::ChainOrType(i, expected, found) => {
+                write!(f, "chain or alternative {} gives {}, expected {}", i, found, expected)
+            }
+            InvalidParserError::BlankError => write!(f, "unexpected error in blank parser"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum ParsingError {
     // Trying to parse with an invalid parser
     InvalidParser(InvalidParserError),
@@ -104,104 +479,1035 @@ pub enum ParsingError {
     UnexpectedError,
     // Tag is not equal to parsed value
     TagNotEqual,
+    // Required whitespace is missing
+    ExpectedBlank,
+    // Not at a \n or \r\n
+    ExpectedLineEnding,
+    // None of the alternatives of an or matched, holds the error
+    // of each alternative in order
+    NoAlternative(Vec<ParseError>),
+    // Partial input ran out, at least this many more bytes are needed
+    Incomplete(usize),
+    // The parser of a not matched
+    UnexpectedMatch,
+    // The parse has no grammar with this rule
+    UnknownRule(String),
+    // Rules nested deeper than the grammar allows
+    TooDeep(usize),
 }
 
-fn run_parser<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<Option<&'d str>, ParsingError> {
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParsingError::InvalidParser(ref e) => write!(f, "invalid parser: {}", e),
+            ParsingError::InsufficientData => write!(f, "not enough data left"),
+            ParsingError::InvalidIndex => write!(f, "not on a utf8 char boundary"),
+            ParsingError::ChainAfterSkip => write!(f, "skip cannot have a chain"),
+            ParsingError::ChainError(ref e) => write!(f, "{}", e),
+            ParsingError::EmptyBuffer => write!(f, "nothing left to parse"),
+            ParsingError::InvalidPWord => write!(f, "pword must start with a letter or '_'"),
+            ParsingError::UnexpectedError => write!(f, "unexpected error"),
+            ParsingError::TagNotEqual => write!(f, "tag does not match"),
+            ParsingError::ExpectedBlank => write!(f, "expected whitespace"),
+            ParsingError::ExpectedLineEnding => write!(f, "expected a line ending"),
+            ParsingError::NoAlternative(ref errors) => {
+                write!(f, "none of the {} alternatives matched", errors.len())
+            }
+            ParsingError::Incomplete(needed) => write!(f, "needs at least {} more bytes", needed),
+            ParsingError::UnexpectedMatch => write!(f, "matched what not excludes"),
+            ParsingError::UnknownRule(ref name) => write!(f, "no rule named `{}`", name),
+            ParsingError::TooDeep(depth) => write!(f, "rules nested deeper than {}", depth),
+        }
+    }
+}
+
+/// Region of the input a failing parser was looking at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    /// Length in bytes, zero when the parser has no fixed width
+    pub len: usize,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column in chars, starting at 1
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ParsingError,
+    pub span: Span,
+    /// Index of the failing parser at each level of the tree, starting
+    /// from the root. `[9]` is the tenth parser of the top sequence.
+    pub path: Vec<usize>,
+}
+
+impl ParseError {
+    // Runs only keep track of offsets, lines and columns are worked out
+    // once the parse has failed
+    pub(crate) fn locate(&mut self, input: &[u8]) {
+        let before = &input[..self.span.offset];
+        let line_start = match before.iter().rposition(|&b| b == b'\n') {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        self.span.line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        // utf8 continuation bytes don't start a char
+        self.span.column = before[line_start..].iter().filter(|&&b| b & 0xC0 != 0x80).count() + 1;
+
+        if let ParsingError::NoAlternative(ref mut errors) = self.kind {
+            for e in errors.iter_mut() {
+                e.locate(input);
+            }
+        }
+    }
+
+    // Moves a located error from a piece of the input to where that
+    // piece starts, `lines` is the number of lines before it and `column`
+    // the number of chars before it on its first line
+    pub(crate) fn shift(&mut self, offset: usize, lines: usize, column: usize) {
+        self.span.offset += offset;
+        if self.span.line == 1 {
+            self.span.column += column;
+        }
+        self.span.line += lines;
+
+        if let ParsingError::NoAlternative(ref mut errors) = self.kind {
+            for e in errors.iter_mut() {
+                e.shift(offset, lines, column);
+            }
+        }
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ParsingError::Incomplete(_))
+    }
+
+    // Backtracking can't get past these, they end the whole parse
+    fn is_final(&self) -> bool {
+        matches!(self.kind, ParsingError::Incomplete(_) | ParsingError::TooDeep(_))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.kind, self.span.line, self.span.column)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Structural problem found by `Parser::validate`
+#[derive(Debug)]
+pub struct ValidationError {
+    pub kind: InvalidParserError,
+    /// Index of the offending parser at each level of the tree
+    pub path: Vec<usize>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:?}", self.kind, self.path)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// State shared by every parser during a single run
+struct Run<'p, 'c: 'p, 't: 'p> {
+    // Length of the whole input, offsets are measured against it
+    input_len: usize,
+    // Index of the current parser at each level of the tree
+    path: Vec<usize>,
+    // More input may follow the buffer
+    partial: bool,
+    // Where `rule` parsers find their parsers
+    grammar: Option<&'p Grammar<'c, 't>>,
+    // How many rules are running inside each other
+    depth: usize,
+    // Results already known, when the grammar memoizes
+    memo: Option<Memo<'p, 'c>>,
+    tx: Transaction<'p, 'c>,
+}
+
+impl<'p, 'c, 't> Run<'p, 'c, 't> {
+    fn new(input_len: usize,
+           partial: bool,
+           output: Option<&'p mut dyn Any>,
+           grammar: Option<&'p Grammar<'c, 't>>)
+           -> Run<'p, 'c, 't> {
+        Run {
+            input_len,
+            path: vec![],
+            partial,
+            grammar,
+            depth: 0,
+            memo: match grammar {
+                Some(g) if g.memoizes() => Some(Memo { results: HashMap::new(), counts: vec![], stats: MemoStats::default() }),
+                _ => None,
+            },
+            tx: Transaction::new(output),
+        }
+    }
+
+    // Every buffer a parser gets is a suffix of the input, so its offset
+    // is whatever has been cut from the front
+    fn error<'d, I: Input<'d>>(&self, kind: ParsingError, buffer: I, len: usize) -> ParseError {
+        ParseError {
+            kind,
+            span: Span {
+                offset: self.input_len - buffer.input_len(),
+                len: len.min(buffer.input_len()),
+                line: 0,
+                column: 0,
+            },
+            path: self.path.clone(),
+        }
+    }
+}
+
+fn run_parser<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    // Repetitions open a record for each match instead
+    let name = match parser.name {
+        Some(ref name) if !repeats(parser) => Some(name.as_str()),
+        _ => None,
+    };
+    if name.is_some() {
+        run.tx.begin(name, None);
+    }
+    let result = run_node(parser, buffer, run);
+    if name.is_some() && result.is_ok() {
+        run.tx.end();
+    }
+    result
+}
+
+// Names the captures of `parser` end up under in the record around it,
+// a named parser's are in its own record
+fn list_names(parser: &Parser, names: &mut Vec<String>) {
+    if let Some(ref name) = parser.name {
+        names.push(name.clone());
+        return;
+    }
+    if let Some(ref chain) = parser.chain {
+        chain_captures(chain, names);
+    }
+    for child in parser.children() {
+        list_names(child, names);
+    }
+}
+
+fn chain_captures(chain: &Chain, names: &mut Vec<String>) {
+    for op in chain.olist.iter() {
+        match *op {
+            Operation::Capture(ref name) => names.push(name.clone()),
+            Operation::Opt(ref c) => chain_captures(c, names),
+            Operation::Or(ref chains) => {
+                for c in chains.iter() {
+                    chain_captures(c, names);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn repeats(parser: &Parser) -> bool {
+    matches!(parser.ptype,
+             ParserType::Many0(_) |
+             ParserType::Many1(_) |
+             ParserType::Count(_, _) |
+             ParserType::SepBy(_, _))
+}
+
+fn run_node<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
     match parser.ptype {                
         ParserType::Sequence(_) => {
-            let result = run_seq(parser, buffer);
+            let result = run_seq(parser, buffer, run);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Or(_) => {
+            let result = run_memoized(parser, buffer, run, |run| run_or(parser, buffer, run));
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Many0(_) |
+        ParserType::Many1(_) |
+        ParserType::Count(_, _) |
+        ParserType::SepBy(_, _) => {
+            let result = run_repeat(parser, buffer, run);
             match result {
-                Ok(()) => return Ok(None),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Opt(_) |
+        ParserType::Peek(_) |
+        ParserType::Not(_) => {
+            let result = run_look(parser, buffer, run);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Delimited(_, _, _) => {
+            let result = run_delimited(parser, buffer, run);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Balanced(_, _) => {
+            let result = run_balanced(parser, buffer, run.partial, &mut run.tx);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
+            }
+        }
+        ParserType::Rule(_) => {
+            let result = run_rule(parser, buffer, run);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
+            }
+        }
+        ParserType::Precedence(_) => {
+            let result = run_precedence(parser, buffer, run);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(pe),
             }
         }
         ParserType::Take(_) => {
-            let result = run_take(parser, buffer);
+            let result = run_take(parser, buffer, run.partial, &mut run.tx);
             match result {
-                Ok(rest) => return Ok(Some(rest)),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
             }
         }
         ParserType::Tag(_) => {
-            let result = run_tag(parser, buffer);
+            let result = run_tag(parser, buffer, run.partial, &mut run.tx);
             match result {
-                Ok(rest) => return Ok(Some(rest)),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
             }
         }
         ParserType::Skip(_) => {
-            let result = run_skip(parser, buffer);
+            let result = run_skip(parser, buffer, run.partial);
             match result {
-                Ok(rest) => return Ok(Some(rest)),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
             }
         }
         ParserType::PWord => {
-            let result = run_pword(parser, buffer);
+            let result = run_pword(parser, buffer, run.partial, &mut run.tx);
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
+            }
+        }
+        ParserType::Until(_, _) => {
+            let result = run_until(parser, buffer, run.partial, &mut run.tx);
             match result {
-                Ok(rest) => return Ok(Some(rest)),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
             }
         }
-        ParserType::Blank => {
-            let result = run_blank(parser, buffer);
+        ParserType::Blank(_) => {
+            let result = run_blank(parser, buffer, run.partial, &mut run.tx);
             match result {
-                Ok(rest) => return Ok(Some(rest)),
-                Err(pe) => return Err(pe),
+                Ok(rest) => Ok(rest),
+                Err(pe) => Err(run.error(pe, buffer, span_len(parser))),
             }
         }
     }
 }
 
-fn run_seq<'d, 'c, 't>(seq_parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<(), ParsingError> {
+// Width of the input a leaf parser looks at, used for error spans
+fn span_len(parser: &Parser) -> usize {
+    match parser.ptype {
+        ParserType::Take(count) | ParserType::Skip(count) => count,
+        ParserType::Tag(value) => value.len(),
+        _ => 0,
+    }
+}
+
+// Runs the `index`th child of a parser, keeping the path up to date
+fn run_child<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, index: usize, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    run.path.push(index);
+    let result = run_parser(parser, buffer, run);
+    run.path.pop();
+    result
+}
+
+fn run_seq<'d, 'p, 'c, 't, I: Input<'d>>(seq_parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
     match seq_parser.ptype {
-        ParserType::Sequence(ref mut list) => {
+        ParserType::Sequence(ref list) => {
             let mut data = buffer;
 
-            for parser in list.iter_mut() {
-                let result = run_parser(parser, data);
+            for (i, parser) in list.iter().enumerate() {
+                let result = run_child(parser, i, data, run);
                 match result {
                     Err(e) => return Err(e),
-                    Ok(Some(rest)) => data = rest,
-                    Ok(None) => ()
+                    Ok(rest) => data = rest,
                 }
             }
+            Ok(data)
         }
-        _ => return Err(ParsingError::InvalidParser(InvalidParserError::SeqNot)),
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::SeqNot), buffer, 0)),
     }
-    Ok(())
 }
 
-fn run_blank<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<&'d str, ParsingError> {
-    if let ParserType::PWord = parser.ptype {
-        let ut = utils::blank(buffer);
+fn run_or<'d, 'p, 'c, 't, I: Input<'d>>(or_parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    match or_parser.ptype {
+        ParserType::Or(ref list) => {
+            let mut errors = Vec::with_capacity(list.len());
+
+            // Every alternative gets the same slice, so rewinding after a
+            // failed attempt is just not advancing past `buffer` and
+            // dropping whatever it stored
+            for (i, parser) in list.iter().enumerate() {
+                let mark = run.tx.mark();
+                let result = run_child(parser, i, buffer, run);
+                match result {
+                    Ok(rest) => return Ok(rest),
+                    // can't tell if a later alternative is the right one
+                    Err(e) if e.is_final() => return Err(e),
+                    Err(e) => {
+                        run.tx.rollback(mark);
+                        errors.push(e);
+                    }
+                }
+            }
+            Err(run.error(ParsingError::NoAlternative(errors), buffer, 0))
+        }
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::OrNot), buffer, 0)),
+    }
+}
+
+/// What running a parser at an offset gave, for grammars that memoize
+struct Memo<'p, 'c: 'p> {
+    // By the address of the parser, the offset it ran at and how deep in
+    // rules it was, a match may be too deep further in
+    results: HashMap<(usize, usize, usize), Memoized<'p, 'c>>,
+    // Every repetition count set while memoizing, in order, so a replay
+    // can set them again
+    counts: Vec<(&'p Cell<Option<usize>>, usize)>,
+    stats: MemoStats,
+}
+
+enum Memoized<'p, 'c: 'p> {
+    // Length of the input left, what the parser staged and the counts
+    // its repetitions set
+    Matched(usize, Vec<Staged<'p, 'c>>, Vec<(&'p Cell<Option<usize>>, usize)>),
+    // Its path is relative to the parser that failed
+    Failed(ParseError, Vec<(&'p Cell<Option<usize>>, usize)>),
+}
+
+// Runs `body` for `parser`, or replays what it did the last time it ran
+// at this offset
+fn run_memoized<'d, 'p, 'c, 't, I, F>(parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>, body: F) -> Result<I, ParseError>
+    where I: Input<'d>,
+          F: FnOnce(&mut Run<'p, 'c, 't>) -> Result<I, ParseError>
+{
+    let key = (parser as *const Parser as usize, run.input_len - buffer.input_len(), run.depth);
+    let memo = match run.memo {
+        Some(ref mut memo) => memo,
+        None => return body(run),
+    };
+    let known = match memo.results.get(&key) {
+        Some(Memoized::Matched(rest_len, staged, counts)) => {
+            let output = &mut run.tx.output;
+            let copies: Option<Vec<_>> = staged.iter().map(|s| s.copy(output)).collect();
+            copies.map(|copies| (Ok((*rest_len, copies)), counts.clone()))
+        }
+        Some(Memoized::Failed(e, counts)) => Some((Err(e.clone()), counts.clone())),
+        None => None,
+    };
+    match known {
+        Some((result, counts)) => {
+            memo.stats.hits += 1;
+            for &(cell, count) in counts.iter() {
+                cell.set(Some(count));
+            }
+            memo.counts.extend(counts);
+            match result {
+                Ok((rest_len, staged)) => {
+                    run.tx.staged.extend(staged);
+                    match buffer.split_at_byte(buffer.input_len() - rest_len) {
+                        Ok(split) => return Ok(split.right),
+                        Err(_) => return Err(run.error(ParsingError::UnexpectedError, buffer, 0)),
+                    }
+                }
+                Err(mut e) => {
+                    let mut path = run.path.clone();
+                    path.append(&mut e.path);
+                    e.path = path;
+                    return Err(e);
+                }
+            }
+        }
+        None => memo.stats.misses += 1,
+    }
+
+    let mark = run.tx.mark();
+    let counted = memo.counts.len();
+    let result = body(run);
+    let counts = match run.memo {
+        Some(ref memo) => memo.counts[counted..].to_vec(),
+        None => vec![],
+    };
+    let memoized = match result {
+        Ok(rest) => {
+            let tx = &mut run.tx;
+            let output = &mut tx.output;
+            let staged: Option<Vec<_>> = tx.staged[mark..].iter().map(|s| s.copy(output)).collect();
+            staged.map(|staged| Memoized::Matched(rest.input_len(), staged, counts))
+        }
+        // Fails only this deep, it may match from somewhere shallower
+        Err(ParseError { kind: ParsingError::TooDeep(_), .. }) => None,
+        Err(ref e) => {
+            let mut e = e.clone();
+            e.path = e.path.split_off(run.path.len().min(e.path.len()));
+            Some(Memoized::Failed(e, counts))
+        }
+    };
+    if let (Some(memo), Some(memoized)) = (run.memo.as_mut(), memoized) {
+        memo.results.insert(key, memoized);
+    }
+    result
+}
+
+// Errors inside the rule point at the rule, its parser isn't part of the
+// tree a path walks
+fn run_rule<'d, 'p, 'c, 't, I: Input<'d>>(rule_parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    match rule_parser.ptype {
+        ParserType::Rule(name) => {
+            let (grammar, parser) = match run.grammar.and_then(|g| g.get(name).map(|p| (g, p))) {
+                Some(found) => found,
+                None => return Err(run.error(ParsingError::UnknownRule(name.to_string()), buffer, 0)),
+            };
+            if run.depth >= grammar.depth_limit() {
+                return Err(run.error(ParsingError::TooDeep(grammar.depth_limit()), buffer, 0));
+            }
+            run.depth += 1;
+            let result = run_memoized(parser, buffer, run, |run| run_parser(parser, buffer, run));
+            run.depth -= 1;
+            match result {
+                Ok(rest) => Ok(rest),
+                Err(mut pe) => {
+                    pe.path.truncate(run.path.len());
+                    Err(pe)
+                }
+            }
+        }
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::RuleNot), buffer, 0)),
+    }
+}
+
+fn run_precedence<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    match parser.ptype {
+        ParserType::Precedence(ref expression) => {
+            let (climbed, rest) = run_expression(&expression.operators, buffer, buffer, 0, 0, run)?;
+            // Folds take text, a byte parser may have stopped inside a char
+            let matched = &buffer.as_bytes()[..buffer.input_len() - rest.input_len()];
+            let text = match str::from_utf8(matched) {
+                Ok(text) if climbed.on_chars(text) => text.to_string(),
+                _ => return Err(run.error(ParsingError::InvalidIndex, buffer, 0)),
+            };
+            run.tx.build(&*expression.build, climbed, text);
+            Ok(rest)
+        }
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::PrecedenceNot), buffer, 0)),
+    }
+}
+
+// Runs an expression over the whole of `input` on its own, what its
+// parsers stage is dropped. Gives what it matched and where it ends.
+pub(crate) fn climb<'c, 't>(operators: &Operators<'c, 't>,
+                            input: &str,
+                            grammar: Option<&Grammar<'c, 't>>)
+                            -> Result<(Climbed, usize), ParseError> {
+    let mut run = Run::new(input.len(), false, None, grammar);
+    match run_expression(operators, input, input, 0, 0, &mut run) {
+        Ok((climbed, rest)) if climbed.on_chars(input) => Ok((climbed, input.len() - rest.len())),
+        Ok(_) => {
+            let mut pe = run.error(ParsingError::InvalidIndex, input, 0);
+            pe.locate(input.as_bytes());
+            Err(pe)
+        }
+        Err(mut pe) => {
+            pe.locate(input.as_bytes());
+            Err(pe)
+        }
+    }
+}
+
+// Where `at` is in the expression that started at `start`
+fn span<'d, I: Input<'d>>(start: I, from: I, to: I) -> (usize, usize) {
+    (start.input_len() - from.input_len(), start.input_len() - to.input_len())
+}
+
+// Parses from `at` while the operators bind at least as tight as `min`
+fn run_expression<'d, 'p, 'c, 't, I: Input<'d>>(operators: &'p Operators<'c, 't>,
+                                               start: I,
+                                               at: I,
+                                               min: u32,
+                                               depth: usize,
+                                               run: &mut Run<'p, 'c, 't>)
+                                               -> Result<(Climbed, I), ParseError> {
+    let at = skip_blank(operators, at, run)?;
+    let (mut tree, mut at) = run_operand(operators, start, at, depth, run)?;
+    loop {
+        // The blank goes back if no operator follows it
+        let mark = run.tx.mark();
+        let next = skip_blank(operators, at, run)?;
+        let postfix = longest(operators, Part::Postfix, operators.postfixes.iter().map(|op| &op.0), next, run)?;
+        let infix = longest(operators, Part::Infix, operators.infixes.iter().map(|op| &op.0), next, run)?;
+        match (postfix, infix) {
+            (Some((i, len)), other) if other.is_none_or(|(_, l)| len >= l) => {
+                if operators.postfixes[i].1 < min {
+                    run.tx.rollback(mark);
+                    return Ok((tree, at));
+                }
+                let rest = run_child(&operators.postfixes[i].0, operators.index(Part::Postfix, i), next, run)?;
+                tree = Climbed::Postfix(i, span(start, next, rest), Box::new(tree));
+                at = rest;
+            }
+            (_, Some((i, _))) => {
+                let (ref parser, precedence, assoc) = operators.infixes[i];
+                if precedence < min {
+                    run.tx.rollback(mark);
+                    return Ok((tree, at));
+                }
+                let right_min = match assoc {
+                    Assoc::Left => precedence + 1,
+                    Assoc::Right => precedence,
+                };
+                let rest = run_child(parser, operators.index(Part::Infix, i), next, run)?;
+                let (right, end) = run_nested(operators, start, rest, right_min, depth, run)?;
+                tree = Climbed::Infix(i, span(start, next, rest), Box::new(tree), Box::new(right));
+                at = end;
+            }
+            _ => {
+                run.tx.rollback(mark);
+                return Ok((tree, at));
+            }
+        }
+    }
+}
+
+// An expression one level deeper than `depth`, the levels are counted
+// as `run_rule` counts rules
+fn run_nested<'d, 'p, 'c, 't, I: Input<'d>>(operators: &'p Operators<'c, 't>,
+                                           start: I,
+                                           at: I,
+                                           min: u32,
+                                           depth: usize,
+                                           run: &mut Run<'p, 'c, 't>)
+                                           -> Result<(Climbed, I), ParseError> {
+    if depth >= operators.depth_limit {
+        return Err(run.error(ParsingError::TooDeep(operators.depth_limit), at, 0));
+    }
+    run_expression(operators, start, at, min, depth + 1, run)
+}
+
+// What an operand starts with
+enum Start {
+    Prefix(usize),
+    Group(usize),
+    Atom(usize),
+}
+
+fn run_operand<'d, 'p, 'c, 't, I: Input<'d>>(operators: &'p Operators<'c, 't>,
+                                            start: I,
+                                            at: I,
+                                            depth: usize,
+                                            run: &mut Run<'p, 'c, 't>)
+                                            -> Result<(Climbed, I), ParseError> {
+    let mut errors = vec![];
+    let mut found: Option<(Start, usize)> = None;
+    let starts = operators.prefixes.iter().enumerate().map(|(i, op)| (Start::Prefix(i), Part::Prefix, i, &op.0))
+        .chain(operators.groups.iter().enumerate().map(|(i, g)| (Start::Group(i), Part::Open, i, &g.0)))
+        .chain(operators.atoms.iter().enumerate().map(|(i, a)| (Start::Atom(i), Part::Atom, i, a)));
+    for (kind, part, i, parser) in starts {
+        match try_match(parser, operators.index(part, i), at, run) {
+            // A prefix or an open that matches nothing would nest forever
+            Ok(len) if len == 0 && part != Part::Atom => (),
+            Ok(len) => {
+                if found.as_ref().is_none_or(|&(_, l)| len > l) {
+                    found = Some((kind, len));
+                }
+            }
+            Err(pe) if pe.is_final() => return Err(pe),
+            Err(pe) => errors.push(pe),
+        }
+    }
+
+    match found {
+        Some((Start::Prefix(i), _)) => {
+            let (ref parser, precedence) = operators.prefixes[i];
+            let rest = run_child(parser, operators.index(Part::Prefix, i), at, run)?;
+            let (operand, end) = run_nested(operators, start, rest, precedence, depth, run)?;
+            Ok((Climbed::Prefix(i, span(start, at, rest), Box::new(operand)), end))
+        }
+        Some((Start::Group(i), _)) => {
+            let (ref open, ref close) = operators.groups[i];
+            let rest = run_child(open, operators.index(Part::Open, i), at, run)?;
+            let (tree, end) = run_nested(operators, start, rest, 0, depth, run)?;
+            let end = skip_blank(operators, end, run)?;
+            let end = run_child(close, operators.index(Part::Close, i), end, run)?;
+            Ok((tree, end))
+        }
+        Some((Start::Atom(i), _)) => {
+            let rest = run_child(&operators.atoms[i], operators.index(Part::Atom, i), at, run)?;
+            Ok((Climbed::Atom(i, span(start, at, rest)), rest))
+        }
+        None => {
+            if errors.len() == 1 {
+                return Err(errors.remove(0));
+            }
+            Err(run.error(ParsingError::NoAlternative(errors), at, 0))
+        }
+    }
+}
+
+fn skip_blank<'d, 'p, 'c, 't, I: Input<'d>>(operators: &'p Operators<'c, 't>, at: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    match run_step(&operators.blank, operators.index(Part::Blank, 0), at, run) {
+        Ok(rest) => Ok(rest),
+        Err(pe) if pe.is_final() => Err(pe),
+        Err(_) => Ok(at),
+    }
+}
+
+// Length `parser` would match at `at`, nothing it stages is kept. The
+// candidate that wins is run again for real.
+fn try_match<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, index: usize, at: I, run: &mut Run<'p, 'c, 't>) -> Result<usize, ParseError> {
+    let mark = run.tx.mark();
+    let result = run_child(parser, index, at, run);
+    run.tx.rollback(mark);
+    result.map(|rest| at.input_len() - rest.input_len())
+}
+
+// The first of the longest operators at `at` that match something, as
+// its index and length
+fn longest<'d, 'p, 'c: 'p, 't: 'p, I, P>(operators: &'p Operators<'c, 't>,
+                                         part: Part,
+                                         parsers: P,
+                                         at: I,
+                                         run: &mut Run<'p, 'c, 't>)
+                                         -> Result<Option<(usize, usize)>, ParseError>
+    where I: Input<'d>,
+          P: Iterator<Item = &'p Parser<'c, 't>>
+{
+    let mut found: Option<(usize, usize)> = None;
+    for (i, parser) in parsers.enumerate() {
+        match try_match(parser, operators.index(part, i), at, run) {
+            Ok(len) if len > 0 && found.is_none_or(|(_, l)| len > l) => found = Some((i, len)),
+            Err(pe) if pe.is_final() => return Err(pe),
+            _ => (),
+        }
+    }
+    Ok(found)
+}
+
+fn run_delimited<'d, 'p, 'c, 't, I: Input<'d>>(delimited: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    match delimited.ptype {
+        ParserType::Delimited(ref open, ref parser, ref close) => {
+            let mut data = buffer;
+            let mut index = 0;
+            if let Some(ref open) = *open {
+                data = run_child(open, index, data, run)?;
+                index += 1;
+            }
+            let start = data;
+            data = run_child(parser, index, data, run)?;
+            index += 1;
+            // What the inner parser consumed
+            let matched = match start.split_at_byte(start.input_len() - data.input_len()) {
+                Ok(split) => split.left,
+                Err(_) => return Err(run.error(ParsingError::UnexpectedError, start, 0)),
+            };
+            if let Err(pe) = run_chain(delimited, matched, &mut run.tx) {
+                return Err(run.error(pe, start, matched.input_len()));
+            }
+            if let Some(ref close) = *close {
+                data = run_child(close, index, data, run)?;
+            }
+            Ok(data)
+        }
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::DelimitedNot), buffer, 0)),
+    }
+}
+
+fn run_balanced<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
+    if let ParserType::Balanced(open, close) = parser.ptype {
+        let ut = utils::balanced(buffer, open, close);
         match ut {
+            Ok(utils::Split { left, right }) => {
+                // The chain doesn't see the delimiters
+                let inner = left.split_at_byte(left.input_len() - close.len())
+                    .and_then(|split| split.left.split_at_byte(open.len()));
+                let chain_result = match inner {
+                    Ok(utils::Split { right: inner, .. }) => run_chain(parser, inner, tx),
+                    Err(_) => return Err(ParsingError::UnexpectedError),
+                };
+                match chain_result {
+                    Err(pe) => return Err(pe),
+                    Ok(_) => return Ok(right),
+                }
+            }
+            Err(utils::Error::TagNotEqual) => return Err(ParsingError::TagNotEqual),
+            // Still open, the close may be in the rest of the input
+            Err(utils::Error::InsufficientBuffer) if partial => return Err(ParsingError::Incomplete(1)),
+            Err(utils::Error::InsufficientBuffer) => return Err(ParsingError::InsufficientData),
+            Err(utils::Error::InvalidCharBoundary) => return Err(ParsingError::InvalidIndex),
+            Err(_) => return Err(ParsingError::UnexpectedError),
+        }
+    }
+    Err(ParsingError::InvalidParser(InvalidParserError::BalancedNot))
+}
+
+// Opt, peek and not. Only an opt that matches consumes input or keeps
+// what it stored, lookaheads drop everything once they know.
+fn run_look<'d, 'p, 'c, 't, I: Input<'d>>(look_parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    let mark = run.tx.mark();
+    match look_parser.ptype {
+        ParserType::Opt(ref parser) => {
+            match run_child(parser, 0, buffer, run) {
+                Ok(rest) => Ok(rest),
+                // more input could still make it match
+                Err(e) if e.is_final() => Err(e),
+                Err(_) => {
+                    run.tx.rollback(mark);
+                    Ok(buffer)
+                }
+            }
+        }
+        ParserType::Peek(ref parser) => {
+            let result = run_child(parser, 0, buffer, run);
+            run.tx.rollback(mark);
+            match result {
+                Ok(_) => Ok(buffer),
+                Err(e) => Err(e),
+            }
+        }
+        ParserType::Not(ref parser) => {
+            let result = run_child(parser, 0, buffer, run);
+            run.tx.rollback(mark);
+            match result {
+                Ok(_) => Err(run.error(ParsingError::UnexpectedMatch, buffer, 0)),
+                Err(e) if e.is_final() => Err(e),
+                Err(_) => Ok(buffer),
+            }
+        }
+        _ => Err(run.error(ParsingError::InvalidParser(InvalidParserError::LookNot), buffer, 0)),
+    }
+}
+
+// Runs `parser` as one step of a repetition. If it fails anything it
+// stored is discarded, so the repetition can stop where it is.
+fn run_step<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, index: usize, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    let mark = run.tx.mark();
+    let result = run_child(parser, index, buffer, run);
+    if result.is_err() {
+        run.tx.rollback(mark);
+    }
+    result
+}
+
+// One match of a repetition, undone if it fails
+fn run_item<'d, 'p, 'c, 't, I: Input<'d>>(rep_parser: &'p Parser<'c, 't>,
+                                         parser: &'p Parser<'c, 't>,
+                                         buffer: I,
+                                         run: &mut Run<'p, 'c, 't>)
+                                         -> Result<I, ParseError> {
+    let mark = run.tx.mark();
+    let name = rep_parser.name.as_deref();
+    if name.is_some() {
+        run.tx.begin(name, None);
+    }
+    let result = run_child(parser, 0, buffer, run);
+    match result {
+        Ok(_) if name.is_some() => run.tx.end(),
+        Ok(_) => (),
+        Err(_) => run.tx.rollback(mark),
+    }
+    result
+}
+
+fn run_repeat<'d, 'p, 'c, 't, I: Input<'d>>(rep_parser: &'p Parser<'c, 't>, buffer: I, run: &mut Run<'p, 'c, 't>) -> Result<I, ParseError> {
+    let mut data = buffer;
+    let mut matched = 0;
+    // Captures in here are collected into lists, a named repetition makes
+    // one list of records
+    let lists = match rep_parser.name {
+        Some(ref name) => ::std::slice::from_ref(name),
+        None => rep_parser.lists.as_slice(),
+    };
+    run.tx.begin(None, Some(lists));
+
+    match rep_parser.ptype {
+        ParserType::Many0(ref parser) => {
+            loop {
+                match run_item(rep_parser, parser, data, run) {
+                    Ok(rest) => {
+                        matched += 1;
+                        // A match that consumes nothing would match forever
+                        if rest.input_len() == data.input_len() {
+                            break;
+                        }
+                        data = rest;
+                    }
+                    // More input could make it match again
+                    Err(e) if e.is_final() => return Err(e),
+                    Err(_) => break,
+                }
+            }
+        }
+        ParserType::Many1(ref parser) => {
+            loop {
+                match run_item(rep_parser, parser, data, run) {
+                    Ok(rest) => {
+                        matched += 1;
+                        if rest.input_len() == data.input_len() {
+                            break;
+                        }
+                        data = rest;
+                    }
+                    Err(e) if matched == 0 || e.is_final() => return Err(e),
+                    Err(_) => break,
+                }
+            }
+        }
+        ParserType::Count(times, ref parser) => {
+            while matched < times {
+                match run_item(rep_parser, parser, data, run) {
+                    Ok(rest) => data = rest,
+                    Err(e) => return Err(e),
+                }
+                matched += 1;
+            }
+        }
+        ParserType::SepBy(ref parser, ref separator) => {
+            match run_item(rep_parser, parser, data, run) {
+                Ok(rest) => {
+                    matched += 1;
+                    data = rest;
+                }
+                Err(e) if e.is_final() => return Err(e),
+                Err(_) => (),
+            }
+
+            // The separator is only consumed if the parser after it matches
+            while matched > 0 {
+                let mark = run.tx.mark();
+                let after_sep = match run_step(separator, 1, data, run) {
+                    Ok(rest) => rest,
+                    Err(e) if e.is_final() => return Err(e),
+                    Err(_) => break,
+                };
+                match run_item(rep_parser, parser, after_sep, run) {
+                    Ok(rest) => {
+                        matched += 1;
+                        if rest.input_len() == data.input_len() {
+                            break;
+                        }
+                        data = rest;
+                    }
+                    Err(e) if e.is_final() => return Err(e),
+                    Err(_) => {
+                        run.tx.rollback(mark);
+                        break;
+                    }
+                }
+            }
+        }
+        _ => return Err(run.error(ParsingError::InvalidParser(InvalidParserError::RepeatNot), buffer, 0)),
+    }
+    run.tx.end();
+    rep_parser.iterations.set(Some(matched));
+    if let Some(ref mut memo) = run.memo {
+        memo.counts.push((&rep_parser.iterations, matched));
+    }
+    Ok(data)
+}
+
+fn run_blank<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
+    if let ParserType::Blank(kind) = parser.ptype {
+        let ut = match kind {
+            BlankKind::Blank0 => utils::blank(buffer),
+            BlankKind::Blank1 => utils::blank1(buffer),
+            BlankKind::Space0 => utils::space(buffer),
+            BlankKind::Space1 => utils::space1(buffer),
+            BlankKind::LineEnding => utils::line_ending(buffer),
+        };
+        match ut {
+            // Whitespace that reaches the end of partial input may go on
+            Ok(utils::Split { right, .. }) if partial && kind != BlankKind::LineEnding && right.input_len() == 0 => {
+                return Err(ParsingError::Incomplete(1))
+            }
             Ok(utils::Split {left, right}) => {
-                parser.parsed = Some(left);
-                let chain_result = run_chain(parser);
+                let chain_result = run_chain(parser, left, tx);
                 match chain_result {
-                    Err(ce) => return Err(ParsingError::ChainError(ce)),
+                    Err(pe) => return Err(pe),
                     Ok(_) => return Ok(right),
                 }
             }
+            Err(utils::Error::ExpectedBlank) if partial && buffer.input_len() == 0 => return Err(ParsingError::Incomplete(1)),
+            Err(utils::Error::ExpectedLineEnding) if partial && buffer.as_bytes() == b"\r" => return Err(ParsingError::Incomplete(1)),
+            Err(utils::Error::EmptyBuffer) if partial => return Err(ParsingError::Incomplete(1)),
+            Err(utils::Error::ExpectedBlank) => return Err(ParsingError::ExpectedBlank),
+            Err(utils::Error::ExpectedLineEnding) => return Err(ParsingError::ExpectedLineEnding),
+            Err(utils::Error::EmptyBuffer) => return Err(ParsingError::EmptyBuffer),
             _ => return Err(ParsingError::InvalidParser(InvalidParserError::BlankError)),
         }
     }
     Err(ParsingError::InvalidParser(InvalidParserError::BlankNot))
 }
 
-fn run_pword<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<&'d str, ParsingError> {
+fn run_until<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
+    if let ParserType::Until(ref terminators, ref escapes) = parser.ptype {
+        let ut = utils::until_escaped(buffer, terminators, escapes);
+        match ut {
+            // No terminator yet, it may be in the rest of the input
+            Ok(utils::Split { right, .. }) if partial && right.input_len() == 0 => {
+                return Err(ParsingError::Incomplete(1))
+            }
+            Ok(utils::Split {left, right}) => {
+                let chain_result = run_chain(parser, left, tx);
+                match chain_result {
+                    Err(pe) => return Err(pe),
+                    Ok(_) => return Ok(right),
+                }
+            }
+            Err(_) => return Err(ParsingError::UnexpectedError),
+        }
+    }
+    Err(ParsingError::InvalidParser(InvalidParserError::UntilNot))
+}
+
+fn run_pword<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
     if let ParserType::PWord = parser.ptype {
         let ut = utils::pword(buffer);
         match ut {
+            Ok(utils::Split { right, .. }) if partial && right.input_len() == 0 => {
+                return Err(ParsingError::Incomplete(1))
+            }
             Ok(utils::Split {left, right}) => {
-                parser.parsed = Some(left);
-                let chain_result = run_chain(parser);
+                let chain_result = run_chain(parser, left, tx);
                 match chain_result {
-                    Err(ce) => return Err(ParsingError::ChainError(ce)),
+                    Err(pe) => return Err(pe),
                     Ok(_) => return Ok(right),
                 }
             }
+            Err(utils::Error::EmptyBuffer) if partial => return Err(ParsingError::Incomplete(1)),
             Err(utils::Error::EmptyBuffer) => return Err(ParsingError::EmptyBuffer),
             Err(utils::Error::InvalidPWord) => return Err(ParsingError::InvalidPWord),
             Err(_) => return Err(ParsingError::UnexpectedError)
@@ -211,18 +1517,20 @@ fn run_pword<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Re
     Err(ParsingError::InvalidParser(InvalidParserError::PWordNot))
 }
 
-fn run_skip<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<&'d str, ParsingError> {
+fn run_skip<'d, 'c, 't, I: Input<'d>>(parser: &Parser<'c, 't>, buffer: I, partial: bool) -> Result<I, ParsingError> {
     if let ParserType::Skip(c) = parser.ptype {
         let ut = utils::skip(buffer, c);
         match ut {
-            Ok(utils::Split { left, right }) => {
-                parser.parsed = Some(left);
-                if let Some(_) = parser.chain {
+            Ok(utils::Split { right, .. }) => {
+                if parser.chain.is_some() {
                     return Err(ParsingError::ChainAfterSkip);
                 } else {
                     return Ok(right);
                 }
             }
+            Err(utils::Error::InsufficientBuffer) if partial => {
+                return Err(ParsingError::Incomplete(c - buffer.input_len()))
+            }
             Err(utils::Error::InsufficientBuffer) => return Err(ParsingError::InsufficientData),
             Err(utils::Error::InvalidCharBoundary) => return Err(ParsingError::InvalidIndex),
             Err(_) => return Err(ParsingError::UnexpectedError)
@@ -231,19 +1539,23 @@ fn run_skip<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Res
     Err(ParsingError::InvalidParser(InvalidParserError::SkipNot))
 }
 
-fn run_tag<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<&'d str, ParsingError> {
+fn run_tag<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
     if let ParserType::Tag(value) = parser.ptype {
         let ut = utils::tag(buffer, value);
         match ut {
             Ok(utils::Split { left, right }) => {
-                parser.parsed = Some(left);
-                let chain_result = run_chain(parser);
+                let chain_result = run_chain(parser, left, tx);
                 match chain_result {
-                    Err(ce) => return Err(ParsingError::ChainError(ce)),
+                    Err(pe) => return Err(pe),
                     Ok(_) => return Ok(right),
                 }
             }
             Err(utils::Error::TagNotEqual) => return Err(ParsingError::TagNotEqual),
+            // What there is must already match for more input to help
+            Err(utils::Error::InsufficientBuffer) if partial && value.starts_with(buffer.as_bytes()) => {
+                return Err(ParsingError::Incomplete(value.len() - buffer.input_len()))
+            }
+            Err(utils::Error::InsufficientBuffer) if partial => return Err(ParsingError::TagNotEqual),
             Err(utils::Error::InsufficientBuffer) => return Err(ParsingError::InsufficientData),
             Err(utils::Error::InvalidCharBoundary) => return Err(ParsingError::InvalidIndex),
             Err(_) => return Err(ParsingError::UnexpectedError)
@@ -252,19 +1564,21 @@ fn run_tag<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Resu
     Err(ParsingError::InvalidParser(InvalidParserError::TakeNot))
 }
 
-fn run_take<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Result<&'d str, ParsingError> {
+fn run_take<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>, buffer: I, partial: bool, tx: &mut Transaction<'p, 'c>) -> Result<I, ParsingError> {
 
     if let ParserType::Take(c) = parser.ptype {
         let ut = utils::take(buffer, c);
         match ut {
             Ok(utils::Split { left, right }) => {
-                parser.parsed = Some(left);
-                let chain_result = run_chain(parser);
+                let chain_result = run_chain(parser, left, tx);
                 match chain_result {
-                    Err(ce) => return Err(ParsingError::ChainError(ce)),
+                    Err(pe) => return Err(pe),
                     Ok(_) => return Ok(right),
                 }
             }
+            Err(utils::Error::InsufficientBuffer) if partial => {
+                return Err(ParsingError::Incomplete(c - buffer.input_len()))
+            }
             Err(utils::Error::InsufficientBuffer) => return Err(ParsingError::InsufficientData),
             Err(utils::Error::InvalidCharBoundary) => return Err(ParsingError::InvalidIndex),
             Err(_) => return Err(ParsingError::UnexpectedError)
@@ -273,16 +1587,27 @@ fn run_take<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>, buffer: &'d str) -> Res
     Err(ParsingError::InvalidParser(InvalidParserError::TakeNot))
 }
 
-#[derive(Debug)]
-enum ChainingError {
+#[derive(Debug, Clone)]
+pub enum ChainingError {
     // Attepting to aply chain to non-existant parsed data
     NoParsedData,
     // Previous chain operation failed to produce data
     PreviousOpFailed,
     // Equality test failed
     EqFailed,
+    // Inequality test failed
+    NeqFailed,
+    // Value is not empty
+    NotEmpty,
+    // None of the chains in an or succeeded, holds the error
+    // of each chain in order
+    NoAlternative(Vec<ChainingError>),
     // Operation expected a &str found i32
     ExpectedFound(ParsingDataTypes, ParsingDataTypes),
+    // Store target can't hold a value of this type
+    InvalidField(ParsingDataTypes),
+    // Store target takes this type, but not this value, like 300 for a u8
+    InvalidValue(ParsingDataTypes),
     // Got a type error when it shouldn't. If this
     // comes up we probabily got a bug, maybe I should just
     // crash
@@ -301,49 +1626,142 @@ enum ChainingError {
     BiggerThenByte,
     // Unexpected erro while trimming string
     TrimError,
+    // Chains work on text and the input is not valid utf8
+    InvalidUtf8,
+    // Decoding needs exactly the first number of bytes, got the second
+    InvalidLength(usize, usize),
+    // store_at needs an output of the type its accessor reads, given
+    // with parse_into
+    NoOutput,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum LastChainData<'a> {
     Str(&'a str),
+    Bytes(&'a [u8]),
     I32(i32),
     F64(f64),
     U8(u8),
     Byte(u8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
     Opt,
-    NotImplemented
 }
-#[derive(Debug)]
-enum ParsingDataTypes {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParsingDataTypes {
     Str,
+    Bytes,
     I32,
     F64,
     U8,
     Byte,
+    U16,
+    I16,
+    U32,
+    U64,
+    I64,
+    F32,
+    // Only in records, never the data of a chain
+    List,
+    Record,
+}
+
+impl fmt::Display for ParsingDataTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParsingDataTypes::Str => write!(f, "&str"),
+            ParsingDataTypes::I32 => write!(f, "i32"),
+            ParsingDataTypes::F64 => write!(f, "f64"),
+            ParsingDataTypes::U8 => write!(f, "u8"),
+            ParsingDataTypes::Byte => write!(f, "byte"),
+            ParsingDataTypes::Bytes => write!(f, "bytes"),
+            ParsingDataTypes::U16 => write!(f, "u16"),
+            ParsingDataTypes::I16 => write!(f, "i16"),
+            ParsingDataTypes::U32 => write!(f, "u32"),
+            ParsingDataTypes::U64 => write!(f, "u64"),
+            ParsingDataTypes::I64 => write!(f, "i64"),
+            ParsingDataTypes::F32 => write!(f, "f32"),
+            ParsingDataTypes::List => write!(f, "list"),
+            ParsingDataTypes::Record => write!(f, "record"),
+        }
+    }
+}
+
+impl fmt::Display for ChainingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChainingError::NoParsedData => write!(f, "no parsed data to chain from"),
+            ChainingError::PreviousOpFailed => write!(f, "previous operation failed"),
+            ChainingError::EqFailed => write!(f, "value is not equal to the expected string"),
+            ChainingError::NeqFailed => write!(f, "value is equal to a rejected string"),
+            ChainingError::NotEmpty => write!(f, "value is not empty"),
+            ChainingError::NoAlternative(ref errors) => {
+                write!(f, "none of the {} chains of the or succeeded", errors.len())
+            }
+            ChainingError::ExpectedFound(expected, found) => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            ChainingError::InvalidField(dtype) => write!(f, "field can't hold a {}", dtype),
+            ChainingError::InvalidValue(dtype) => write!(f, "{} value doesn't fit the field", dtype),
+            ChainingError::InvalidTypeError => write!(f, "unexpected type error"),
+            ChainingError::InvalidI32 => write!(f, "not a valid i32"),
+            ChainingError::InvalidU8 => write!(f, "not a valid u8"),
+            ChainingError::InvalidF64 => write!(f, "not a valid f64"),
+            ChainingError::StoreAfterOpt => write!(f, "store cannot follow opt"),
+            ChainingError::NotImplemented => write!(f, "not implemented"),
+            ChainingError::BiggerThenByte => write!(f, "to_byte needs exactly one byte"),
+            ChainingError::TrimError => write!(f, "unexpected error while trimming"),
+            ChainingError::InvalidUtf8 => write!(f, "not valid utf8"),
+            ChainingError::InvalidLength(expected, found) => {
+                write!(f, "expected {} bytes, found {}", expected, found)
+            }
+            ChainingError::NoOutput => write!(f, "store_at needs an output, see parse_into"),
+        }
+    }
 }
 
 impl<'a> ParsingDataTypes {
     fn chain_type_error(self,
                         found: &LastChainData<'a>)
                         -> Result<LastChainData<'a>, ChainingError> {
-        match found {
-            &LastChainData::Str(_) => Err(ChainingError::ExpectedFound(ParsingDataTypes::Str, self)),
-            &LastChainData::I32(_) => Err(ChainingError::ExpectedFound(ParsingDataTypes::I32, self)),
-            &LastChainData::F64(_) => Err(ChainingError::ExpectedFound(ParsingDataTypes::F64, self)),
-            &LastChainData::U8(_) => Err(ChainingError::ExpectedFound(ParsingDataTypes::U8, self)),
-            &LastChainData::Byte(_) => Err(ChainingError::ExpectedFound(ParsingDataTypes::Byte, self)),
-            &LastChainData::NotImplemented => Err(ChainingError::NotImplemented),
-            &LastChainData::Opt => Err(ChainingError::NotImplemented),
-        }
-    }
-}
-fn run_operation<'c, 'd>(op: &'c mut Operation,
-                         data: LastChainData<'d>)
-                         -> Result<LastChainData<'d>, ChainingError> {
-    match op {
-        &mut Operation::Trim => {
+        match *found {
+            LastChainData::Str(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::Str)),
+            LastChainData::I32(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::I32)),
+            LastChainData::F64(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::F64)),
+            LastChainData::U8(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::U8)),
+            LastChainData::Byte(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::Byte)),
+            LastChainData::Bytes(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::Bytes)),
+            LastChainData::U16(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::U16)),
+            LastChainData::I16(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::I16)),
+            LastChainData::U32(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::U32)),
+            LastChainData::U64(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::U64)),
+            LastChainData::I64(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::I64)),
+            LastChainData::F32(_) => Err(ChainingError::ExpectedFound(self, ParsingDataTypes::F32)),
+            LastChainData::Opt => Err(ChainingError::NotImplemented),
+        }
+    }
+}
+fn run_operation<'d, 'p, 'c>(op: &'p Operation<'c>,
+                             data: LastChainData<'d>,
+                             tx: &mut Transaction<'p, 'c>)
+                             -> Result<LastChainData<'d>, ChainingError> {
+    // Raw bytes from a byte input are read as text by the text operations
+    let data = match data {
+        LastChainData::Bytes(b) if reads_text(op) => match str::from_utf8(b) {
+            Ok(s) => LastChainData::Str(s),
+            Err(_) => return Err(ChainingError::InvalidUtf8),
+        },
+        _ => data,
+    };
+
+    match *op {
+        Operation::Trim => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = utils::trim(s);
                     if let Ok(rest) = result {
                         Ok(LastChainData::Str(rest))
@@ -354,9 +1772,9 @@ fn run_operation<'c, 'd>(op: &'c mut Operation,
                 _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         } 
-        &mut Operation::TrimR => {
+        Operation::TrimR => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = utils::trimr(s);
                     if let Ok(utils::Split {left, ..}) = result {
                         Ok(LastChainData::Str(left))
@@ -367,9 +1785,9 @@ fn run_operation<'c, 'd>(op: &'c mut Operation,
                 _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::TrimL => {
+        Operation::TrimL => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = utils::triml(s);
                     if let Ok(utils::Split {right, ..}) = result {
                         Ok(LastChainData::Str(right))
@@ -380,144 +1798,1084 @@ fn run_operation<'c, 'd>(op: &'c mut Operation,
                 _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::EqualString(ref e) => {
+        Operation::EqualString(ref e) => {
             match data {
-                LastChainData::Str(ref s) if e == s => Ok(LastChainData::Str(s)),
+                LastChainData::Str(s) if e == s => Ok(LastChainData::Str(s)),
                 LastChainData::Str(_) => Err(ChainingError::EqFailed),
                 _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::ToI32 => {
+        Operation::NotEqualString(ref e) => {
+            match data {
+                LastChainData::Str(s) if e != s => Ok(LastChainData::Str(s)),
+                LastChainData::Str(_) => Err(ChainingError::NeqFailed),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
+            }
+        }
+        Operation::Empty => {
+            match data {
+                LastChainData::Str(s) if s.is_empty() => Ok(LastChainData::Str(s)),
+                LastChainData::Str(_) => Err(ChainingError::NotEmpty),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
+            }
+        }
+        Operation::ToI32 => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = s.parse::<i32>();
                     match result {
                         Err(_) => Err(ChainingError::InvalidI32),
                         Ok(i) => Ok(LastChainData::I32(i)),
                     }
                 } 
-                _ => ParsingDataTypes::I32.chain_type_error(&data),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::ToF64 => {
+        Operation::ToF64 => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = s.parse::<f64>();
                     match result {
                         Err(_) => Err(ChainingError::InvalidF64),
                         Ok(f) => Ok(LastChainData::F64(f)),
                     }
                 } 
-                _ => ParsingDataTypes::F64.chain_type_error(&data),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::ToU8 => {
+        Operation::ToU8 => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     let result = s.parse::<u8>();
                     match result {
                         Err(_) => Err(ChainingError::InvalidU8),
                         Ok(i) => Ok(LastChainData::U8(i)),
                     }
                 } 
-                _ => ParsingDataTypes::U8.chain_type_error(&data),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::ToByte => {
+        Operation::ToByte => {
             match data {
-                LastChainData::Str(ref s) => {
+                LastChainData::Str(s) => {
                     if s.len() == 1 {
                         Ok(LastChainData::Byte(s.as_bytes()[0]))
                     } else {
                         Err(ChainingError::BiggerThenByte)
                     }
                 } 
-                _ => ParsingDataTypes::U8.chain_type_error(&data),
+                _ => ParsingDataTypes::Str.chain_type_error(&data),
             }
         }
-        &mut Operation::Store(ref mut field_ref) => {
+        Operation::Bytes => {
             match data {
-                LastChainData::Str(ref s) => {
-                    match field_ref.downcast_mut::<String>() {
-                        Some(as_string) => {
-                            as_string.push_str(s);
-                            Ok(LastChainData::Str(s))
-                        }
-                        None => ParsingDataTypes::Str.chain_type_error(&data),
+                LastChainData::Str(s) => Ok(LastChainData::Bytes(s.as_bytes())),
+                LastChainData::Bytes(_) => Ok(data),
+                _ => ParsingDataTypes::Bytes.chain_type_error(&data),
+            }
+        }
+        Operation::Decode(endian, binary) => {
+            let raw = match data {
+                LastChainData::Str(s) => s.as_bytes(),
+                LastChainData::Bytes(b) => b,
+                _ => return ParsingDataTypes::Bytes.chain_type_error(&data),
+            };
+            if raw.len() != binary.size() {
+                return Err(ChainingError::InvalidLength(binary.size(), raw.len()));
+            }
+            Ok(decode(endian, binary, raw))
+        }
+        // Nothing is written here, the value is staged in the transaction
+        // and only reaches the field when the whole parse succeeds
+        Operation::Store(mode, ref field_ref) => {
+            let value = store_value(mode, &**field_ref.borrow(), data)?;
+            tx.stage(Target::Field(field_ref), mode, value);
+            Ok(data)
+        }
+        Operation::StoreAt(mode, ref accessor) => {
+            let value = match tx.output.as_mut().and_then(|o| accessor.get(&mut **o)) {
+                Some(field) => store_value(mode, field, data)?,
+                None => return Err(ChainingError::NoOutput),
+            };
+            tx.stage(Target::Key(accessor), mode, value);
+            Ok(data)
+        }
+        Operation::Capture(ref name) => {
+            let (_, value) = to_value(data)?;
+            tx.capture(name, value);
+            Ok(data)
+        }
+        // TODO: do something about invalid parsers inside Opt
+        // An opt chain that fails part way drops the values it staged
+        Operation::Opt(ref chain) => {
+            let mark = tx.mark();
+            if run_ops(&chain.olist, data, tx).is_err() {
+                tx.rollback(mark);
+            }
+            Ok(LastChainData::Opt)
+        }
+        // Every chain gets the same data, the first one to succeed
+        // gives the result
+        Operation::Or(ref chains) => {
+            let mut errors = Vec::with_capacity(chains.len());
+            for chain in chains.iter() {
+                let mark = tx.mark();
+                match run_ops(&chain.olist, data, tx) {
+                    Ok(nd) => return Ok(nd),
+                    Err(e) => {
+                        tx.rollback(mark);
+                        errors.push(e);
                     }
                 }
-                LastChainData::I32(i) => {
-                    match field_ref.downcast_mut::<i32>() {
-                        Some(as_i32) => {
-                            *as_i32 = i;
-                            Ok(LastChainData::I32(i))
-                        }
-                        None => ParsingDataTypes::I32.chain_type_error(&data),
-                    }
+            }
+            Err(ChainingError::NoAlternative(errors))
+        }
+    }
+}
+
+// `data` converted to what `field` holds, ready to be written
+fn store_value(mode: StoreMode, field: &dyn Field, data: LastChainData) -> Result<(Value, Box<dyn Any>), ChainingError> {
+    let (dtype, value) = match data {
+        // Bytes straight from a byte input are text, unless the
+        // field is made to hold bytes
+        LastChainData::Bytes(b) if field.holds(mode, ParsingDataTypes::Bytes) => {
+            (ParsingDataTypes::Bytes, Value::Bytes(b.to_vec()))
+        }
+        _ => to_value(data)?,
+    };
+    if !field.holds(mode, dtype) {
+        return Err(ChainingError::InvalidField(dtype));
+    }
+    match field.convert(value.clone()) {
+        Some(converted) => Ok((value, converted)),
+        None => Err(ChainingError::InvalidValue(dtype)),
+    }
+}
+
+// Owned copy of the data, bytes are taken as text
+fn to_value(data: LastChainData) -> Result<(ParsingDataTypes, Value), ChainingError> {
+    let pair = match data {
+        LastChainData::Str(s) => (ParsingDataTypes::Str, Value::Str(s.to_string())),
+        LastChainData::Bytes(b) => {
+            match str::from_utf8(b) {
+                Ok(s) => (ParsingDataTypes::Str, Value::Str(s.to_string())),
+                Err(_) => return Err(ChainingError::InvalidUtf8),
+            }
+        }
+        LastChainData::U16(u) => (ParsingDataTypes::U16, Value::U16(u)),
+        LastChainData::I16(i) => (ParsingDataTypes::I16, Value::I16(i)),
+        LastChainData::U32(u) => (ParsingDataTypes::U32, Value::U32(u)),
+        LastChainData::U64(u) => (ParsingDataTypes::U64, Value::U64(u)),
+        LastChainData::I64(i) => (ParsingDataTypes::I64, Value::I64(i)),
+        LastChainData::F32(f) => (ParsingDataTypes::F32, Value::F32(f)),
+        LastChainData::I32(i) => (ParsingDataTypes::I32, Value::I32(i)),
+        LastChainData::F64(f) => (ParsingDataTypes::F64, Value::F64(f)),
+        LastChainData::U8(u) => (ParsingDataTypes::U8, Value::U8(u)),
+        LastChainData::Byte(b) => (ParsingDataTypes::Byte, Value::U8(b)),
+        LastChainData::Opt => return Err(ChainingError::StoreAfterOpt),
+    };
+    Ok(pair)
+}
+
+fn reads_text(op: &Operation) -> bool {
+    matches!(*op,
+             Operation::Trim |
+             Operation::TrimL |
+             Operation::TrimR |
+             Operation::EqualString(_) |
+             Operation::NotEqualString(_) |
+             Operation::Empty |
+             Operation::ToI32 |
+             Operation::ToF64 |
+             Operation::ToU8 |
+             Operation::ToByte)
+}
+
+// `raw` must be exactly `binary.size()` bytes long
+fn decode<'a>(endian: Endian, binary: Binary, raw: &[u8]) -> LastChainData<'a> {
+    macro_rules! read {
+        ($t:ty, $variant:ident) => {{
+            let mut bytes = [0u8; ::std::mem::size_of::<$t>()];
+            bytes.copy_from_slice(raw);
+            match endian {
+                Endian::Little => LastChainData::$variant(<$t>::from_le_bytes(bytes)),
+                Endian::Big => LastChainData::$variant(<$t>::from_be_bytes(bytes)),
+            }
+        }};
+    }
+
+    match binary {
+        Binary::U16 => read!(u16, U16),
+        Binary::I16 => read!(i16, I16),
+        Binary::U32 => read!(u32, U32),
+        Binary::I32 => read!(i32, I32),
+        Binary::U64 => read!(u64, U64),
+        Binary::I64 => read!(i64, I64),
+        Binary::F32 => read!(f32, F32),
+        Binary::F64 => read!(f64, F64),
+    }
+}
+
+// Type a decode gives
+fn binary_type(binary: Binary) -> ParsingDataTypes {
+    match binary {
+        Binary::U16 => ParsingDataTypes::U16,
+        Binary::I16 => ParsingDataTypes::I16,
+        Binary::U32 => ParsingDataTypes::U32,
+        Binary::I32 => ParsingDataTypes::I32,
+        Binary::U64 => ParsingDataTypes::U64,
+        Binary::I64 => ParsingDataTypes::I64,
+        Binary::F32 => ParsingDataTypes::F32,
+        Binary::F64 => ParsingDataTypes::F64,
+    }
+}
+
+fn run_ops<'d, 'p, 'c>(olist: &'p [Operation<'c>],
+                       data: LastChainData<'d>,
+                       tx: &mut Transaction<'p, 'c>)
+                       -> Result<LastChainData<'d>, ChainingError> {
+    let mut data = data;
+
+    for op in olist.iter() {
+        let result = run_operation(op, data, tx);
+        match result {
+            Ok(nd) => data = nd, 
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(data)
+}
+
+fn validate_aux(parser: &Parser, path: &mut Vec<usize>, errors: &mut Vec<ValidationError>) {
+    let mut found = vec![];
+
+    match parser.ptype {
+        ParserType::Sequence(ref list) if list.is_empty() => found.push(InvalidParserError::SeqEmpty),
+        ParserType::Or(ref list) if list.is_empty() => found.push(InvalidParserError::OrEmpty),
+        ParserType::Take(0) => found.push(InvalidParserError::TakeZero),
+        ParserType::Precedence(ref expression) if expression.operators.atoms.is_empty() => {
+            found.push(InvalidParserError::PrecedenceEmpty)
+        }
+        ParserType::Balanced(open, close) if open.is_empty() || close.is_empty() => {
+            found.push(InvalidParserError::BalancedEmpty)
+        }
+        ParserType::Until(ref terminators, _) if terminators.is_empty() || terminators.contains(&"") => {
+            found.push(InvalidParserError::UntilTerminator)
+        }
+        _ => (),
+    }
+    if let Some(ref chain) = parser.chain {
+        match parser.ptype {
+            ParserType::Sequence(_) => found.push(InvalidParserError::SeqChain),
+            ParserType::Or(_) => found.push(InvalidParserError::OrChain),
+            ParserType::Many0(_) |
+            ParserType::Many1(_) |
+            ParserType::Count(_, _) |
+            ParserType::SepBy(_, _) => found.push(InvalidParserError::RepeatChain),
+            ParserType::Opt(_) |
+            ParserType::Peek(_) |
+            ParserType::Not(_) => found.push(InvalidParserError::LookChain),
+            ParserType::Rule(_) => found.push(InvalidParserError::RuleChain),
+            ParserType::Skip(_) => found.push(InvalidParserError::SkipChain),
+            ParserType::Precedence(_) => found.push(InvalidParserError::PrecedenceChain),
+            _ => {
+                if let Err(e) = check_chain(chain) {
+                    found.push(e);
                 }
-                LastChainData::F64(i) => {
-                    match field_ref.downcast_mut::<f64>() {
-                        Some(as_f64) => {
-                            *as_f64 = i;
-                            Ok(LastChainData::F64(i))
-                        }
-                        None => ParsingDataTypes::F64.chain_type_error(&data),
+                check_chain_types(chain, Some(ParsingDataTypes::Str), &mut found);
+            }
+        }
+    }
+    for kind in found {
+        errors.push(ValidationError { kind, path: path.clone() });
+    }
+
+    for (i, child) in parser.children().into_iter().enumerate() {
+        path.push(i);
+        validate_aux(child, path, errors);
+        path.pop();
+    }
+}
+
+// Follows the type of the value down the chain, `dtype` is None once an
+// opt has swallowed it, and checks every store target can hold the type
+// it gets. Returns the type the chain ends with.
+fn check_chain_types(chain: &Chain,
+                     dtype: Option<ParsingDataTypes>,
+                     errors: &mut Vec<InvalidParserError>)
+                     -> Option<ParsingDataTypes> {
+    let mut current = dtype;
+
+    for op in chain.olist.iter() {
+        let (needs, gives) = match *op {
+            Operation::Trim |
+            Operation::TrimL |
+            Operation::TrimR |
+            Operation::EqualString(_) |
+            Operation::NotEqualString(_) |
+            Operation::Empty => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::Str)),
+            Operation::Bytes => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::Bytes)),
+            Operation::Decode(_, binary) => (Some(ParsingDataTypes::Str), Some(binary_type(binary))),
+            Operation::ToI32 => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::I32)),
+            Operation::ToF64 => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::F64)),
+            Operation::ToU8 => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::U8)),
+            Operation::ToByte => (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::Byte)),
+            Operation::Store(mode, ref field_ref) => {
+                if let Some(found) = current {
+                    if !field_ref.borrow().holds(mode, found) {
+                        errors.push(InvalidParserError::StoreType(found));
                     }
                 }
-                LastChainData::U8(u) => {
-                    match field_ref.downcast_mut::<u8>() {
-                        Some(as_u8) => {
-                            *as_u8 = u;
-                            Ok(LastChainData::U8(u))
-                        }
-                        None => ParsingDataTypes::U8.chain_type_error(&data),
+                (None, current)
+            }
+            Operation::StoreAt(mode, ref accessor) => {
+                if let Some(found) = current {
+                    if !accessor.accepts(mode, found) {
+                        errors.push(InvalidParserError::StoreType(found));
                     }
                 }
-                LastChainData::Byte(b) => {
-                    match field_ref.downcast_mut::<u8>() {
-                        Some(as_u8) => {
-                            *as_u8 = b;
-                            Ok(LastChainData::Byte(b))
+                (None, current)
+            }
+            // Anything can be captured
+            Operation::Capture(_) => (None, current),
+            Operation::Opt(ref c) => {
+                check_chain_types(c, current, errors);
+                (None, None)
+            }
+            // Every alternative must give what the first one gives
+            Operation::Or(ref chains) => {
+                let mut gives = None;
+                for (i, c) in chains.iter().enumerate() {
+                    match (gives, check_chain_types(c, current, errors)) {
+                        (None, found) => gives = found,
+                        (Some(expected), Some(found)) if expected != found => {
+                            errors.push(InvalidParserError::ChainOrType(i, expected, found));
                         }
-                        None => ParsingDataTypes::Byte.chain_type_error(&data),
+                        _ => (),
                     }
                 }
-                LastChainData::Opt => Err(ChainingError::StoreAfterOpt),
-                LastChainData::NotImplemented => Err(ChainingError::NotImplemented),
+                (None, gives)
             }
+        };
+
+        match (needs, current) {
+            (_, None) => {
+                errors.push(InvalidParserError::ChainAfterOpt);
+                return None;
+            }
+            // text operations read bytes as text
+            (Some(ParsingDataTypes::Str), Some(ParsingDataTypes::Bytes)) => current = gives,
+            (Some(expected), Some(found)) if expected != found => {
+                errors.push(InvalidParserError::ChainType(expected, found));
+                return None;
+            }
+            _ => current = gives,
         }
-        // TODO: do something about invalid parsers inside Opt
-        &mut Operation::Opt(ref mut chain) => {
-            let mut opt_data = data;
-            for opt_op in chain.olist.iter_mut() {
-                let result = run_operation(opt_op, opt_data);
-                match result {
-                    Ok(nd) => opt_data = nd, 
-                    _ => break,
+    }
+    current
+}
+
+/// Checks the placement rules of a chain and of every chain nested in it
+fn check_chain(chain: &Chain) -> Result<(), InvalidParserError> {
+    let last = chain.olist.len().saturating_sub(1);
+
+    for (i, op) in chain.olist.iter().enumerate() {
+        match *op {
+            Operation::Or(ref chains) => {
+                if i != last {
+                    return Err(InvalidParserError::ChainOrLast);
+                }
+                for c in chains.iter() {
+                    check_chain(c)?;
                 }
             }
-            Ok(LastChainData::Opt)
+            Operation::Opt(ref c) => check_chain(c)?,
+            _ => (),
         }
-        _ => {
-            Ok(LastChainData::NotImplemented)
+    }
+    Ok(())
+}
+
+fn run_chain<'d, 'p, 'c, 't, I: Input<'d>>(parser: &'p Parser<'c, 't>,
+                                          parsed: I,
+                                          tx: &mut Transaction<'p, 'c>)
+                                          -> Result<(), ParsingError> {
+    if let Some(ref chain) = parser.chain {
+        let data = match parsed.as_text() {
+            Some(s) => LastChainData::Str(s),
+            None => LastChainData::Bytes(parsed.as_bytes()),
+        };
+        if let Err(ce) = run_ops(&chain.olist, data, tx) {
+            return Err(ParsingError::ChainError(ce));
         }
     }
+    Ok(())
 }
 
-fn run_chain<'d, 'c, 't>(parser: &mut Parser<'d, 'c, 't>) -> Result<(), ChainingError> {
-    if let Some(ref mut chain) = parser.chain {
-        if let None = parser.parsed {
-            return Err(ChainingError::NoParsedData);
+type StoreTarget<'c> = RefCell<&'c mut dyn Field>;
+
+/// Where a staged value goes, a field borrowed by the parser or one
+/// found in the output given to parse_into
+#[derive(Clone, Copy)]
+enum Target<'p, 'c: 'p> {
+    Field(&'p StoreTarget<'c>),
+    Key(&'p Accessor<'c>),
+}
+
+/// What a run did that only counts if the whole parse succeeds
+enum Staged<'p, 'c: 'p> {
+    // The value as parsed and as converted for the field
+    Store(Target<'p, 'c>, StoreMode, Value, Box<dyn Any>),
+    Capture(&'p str, Value),
+    // A parser whose captures are grouped, a named one gets a record and
+    // captures in a repeated one are collected into the lists it names
+    Begin(Option<&'p str>, Option<&'p [String]>),
+    End,
+    // An expression and the text it matched, folded when committed
+    Build(&'p (dyn Build + 'c), Climbed, String),
+}
+
+impl<'p, 'c> Staged<'p, 'c> {
+    // A store converts its value again, None if the field is gone
+    fn copy(&self, output: &mut Option<&'p mut dyn Any>) -> Option<Staged<'p, 'c>> {
+        match *self {
+            Staged::Store(target, mode, ref value, _) => {
+                let converted = match target {
+                    Target::Field(field_ref) => field_ref.borrow().convert(value.clone()),
+                    Target::Key(accessor) => match output.as_mut().and_then(|o| accessor.get(&mut **o)) {
+                        Some(field) => field.convert(value.clone()),
+                        None => None,
+                    },
+                };
+                converted.map(|boxed| Staged::Store(target, mode, value.clone(), boxed))
+            }
+            Staged::Capture(name, ref value) => Some(Staged::Capture(name, value.clone())),
+            Staged::Begin(name, lists) => Some(Staged::Begin(name, lists)),
+            Staged::Build(build, ref climbed, ref text) => Some(Staged::Build(build, climbed.clone(), text.clone())),
+            Staged::End => Some(Staged::End),
         }
-        let mut data = LastChainData::Str(parser.parsed.unwrap());
+    }
+}
 
-        for op in chain.olist.iter_mut() {
-            let result = run_operation(op, data);
-            match result {
-                Ok(nd) => data = nd, 
-                Err(e) => return Err(e),
+/// Stores and captures staged by a run. Parsers that backtrack take a
+/// `mark` before trying something and `rollback` to it when it fails,
+/// `commit` writes everything that is left into the fields and builds the
+/// record of the captures.
+struct Transaction<'p, 'c: 'p> {
+    staged: Vec<Staged<'p, 'c>>,
+    output: Option<&'p mut dyn Any>,
+}
+
+// A parser being replayed by commit, only named ones have a record
+struct Scope<'p> {
+    name: Option<&'p str>,
+    record: Option<Record>,
+    repeated: bool,
+}
+
+impl<'p, 'c> Transaction<'p, 'c> {
+    fn new(output: Option<&'p mut dyn Any>) -> Transaction<'p, 'c> {
+        Transaction { staged: vec![], output }
+    }
+
+    fn mark(&self) -> usize {
+        self.staged.len()
+    }
+
+    fn rollback(&mut self, mark: usize) {
+        self.staged.truncate(mark);
+    }
+
+    fn stage(&mut self, target: Target<'p, 'c>, mode: StoreMode, (value, converted): (Value, Box<dyn Any>)) {
+        self.staged.push(Staged::Store(target, mode, value, converted));
+    }
+
+    fn capture(&mut self, name: &'p str, value: Value) {
+        self.staged.push(Staged::Capture(name, value));
+    }
+
+    fn begin(&mut self, name: Option<&'p str>, lists: Option<&'p [String]>) {
+        self.staged.push(Staged::Begin(name, lists));
+    }
+
+    fn end(&mut self) {
+        self.staged.push(Staged::End);
+    }
+
+    fn build(&mut self, build: &'p (dyn Build + 'c), climbed: Climbed, text: String) {
+        self.staged.push(Staged::Build(build, climbed, text));
+    }
+
+    fn commit(self) -> Record {
+        let mut output = self.output;
+        let mut scopes = vec![Scope { name: None, record: Some(Record::new()), repeated: false }];
+        for staged in self.staged {
+            match staged {
+                Staged::Store(Target::Field(field_ref), mode, _, value) => field_ref.borrow_mut().write(mode, value),
+                Staged::Store(Target::Key(accessor), mode, _, value) => {
+                    // Checked when the value was staged
+                    if let Some(field) = output.as_mut().and_then(|o| accessor.get(&mut **o)) {
+                        field.write(mode, value);
+                    }
+                }
+                Staged::Capture(name, value) => put(&mut scopes, name, value),
+                Staged::Begin(name, lists) => {
+                    // Lists exist even when nothing is pushed to them
+                    for list in lists.unwrap_or(&[]) {
+                        declare(&mut scopes, list);
+                    }
+                    scopes.push(Scope { name, record: name.map(|_| Record::new()), repeated: lists.is_some() });
+                }
+                Staged::End => close(&mut scopes),
+                Staged::Build(build, climbed, text) => build.build(&climbed, &text),
             }
         }
+        while scopes.len() > 1 {
+            close(&mut scopes);
+        }
+        scopes.pop().and_then(|root| root.record).unwrap_or_default()
     }
-    Ok(())
-}
\ No newline at end of file
+}
+
+// Into the closest record, pushed when a repetition is in between
+fn put(scopes: &mut [Scope], name: &str, value: Value) {
+    let mut repeated = false;
+    for scope in scopes.iter_mut().rev() {
+        repeated |= scope.repeated;
+        if let Some(ref mut record) = scope.record {
+            if repeated {
+                record.push(name, value);
+            } else {
+                record.insert(name, value);
+            }
+            return;
+        }
+    }
+}
+
+// An empty list in the closest record, unless the name is taken
+fn declare(scopes: &mut [Scope], name: &str) {
+    if let Some(record) = scopes.iter_mut().rev().filter_map(|s| s.record.as_mut()).next() {
+        if record.value(name).is_none() {
+            record.insert(name, Value::List(vec![]));
+        }
+    }
+}
+
+fn close(scopes: &mut Vec<Scope>) {
+    // The root is never closed
+    if scopes.len() < 2 {
+        return;
+    }
+    if let Some(Scope { name: Some(name), record: Some(record), .. }) = scopes.pop() {
+        put(scopes, name, Value::Record(record));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use value::FromParsed;
+    use chain::{self, trimr, trim, empty, push, capture};
+
+    #[test]
+    fn or_test() {
+        let mut serial = 0_i32;
+        {
+            let parser = seq(vec![
+                or(vec![
+                    take(6).chain(trimr().eq_str("ATOM")),
+                    take(6).chain(trimr().eq_str("HETATM")),
+                ]),
+                take(5).chain(trim().to_i32().store(&mut serial)),
+            ]);
+            assert!(parser.parse("HETATM   42").is_ok());
+        }
+        assert_eq!(42, serial);
+    }
+
+    #[test]
+    fn or_no_alternative_test() {
+        let parser = or(vec![
+            take(6).chain(trimr().eq_str("ATOM")),
+            take(6).chain(trimr().eq_str("HETATM")),
+        ]);
+        match parser.parse("TER   ") {
+            Err(ParseError { kind: ParsingError::NoAlternative(errors), .. }) => assert_eq!(2, errors.len()),
+            _ => panic!("or should fail when no alternative matches"),
+        }
+    }
+
+    #[test]
+    fn many0_test() {
+        let mut values: Vec<i32> = vec![];
+        {
+            let parser = many0(take(2).chain(trim().to_i32().push(&mut values)));
+            assert_eq!(Some("x"), parser.parse(" 1 2 3x").unwrap());
+            assert_eq!(Some(3), parser.iterations());
+        }
+        assert_eq!(vec![1, 2, 3], values);
+    }
+
+    #[test]
+    fn many1_test() {
+        let parser = many1(take(2).chain(trim().to_i32()));
+        assert!(parser.parse("x").is_err());
+    }
+
+    #[test]
+    fn sep_by_test() {
+        let mut words: Vec<String> = vec![];
+        {
+            let parser = sep_by(pword().chain(push(&mut words)), tag(","));
+            assert_eq!(Some(",1"), parser.parse("a,b_2,c,1").unwrap());
+            assert_eq!(Some(3), parser.iterations());
+        }
+        assert_eq!(vec!["a", "b_2", "c"], words);
+    }
+
+    #[test]
+    fn chain_or_test() {
+        let mut alt_loc: Vec<u8> = vec![];
+        {
+            let parser = many0(take(1).chain(trimr().or(vec![
+                empty(),
+                chain::to_u8().push(&mut alt_loc),
+            ])));
+            assert_eq!(Some("x"), parser.parse(" 12 x").unwrap());
+        }
+        assert_eq!(vec![1, 2], alt_loc);
+    }
+
+    #[test]
+    fn chain_or_last_test() {
+        let parser = take(1).chain(chain::or(vec![empty()]).trim());
+        match parser.validate() {
+            Err(ref errors) if matches!(errors[0].kind, InvalidParserError::ChainOrLast) => (),
+            _ => panic!("or must be the last operation of a chain"),
+        }
+    }
+
+    #[test]
+    fn chain_or_type_test() {
+        let parser = seq(vec![
+            take(3).chain(trim().or(vec![chain::to_i32(), chain::to_f64(), chain::to_i32()])),
+            take(3).chain(trim().or(vec![chain::to_f64(), chain::to_f64()])),
+        ]);
+        let errors = parser.validate().unwrap_err();
+        let found: Vec<(String, Vec<usize>)> = errors.iter()
+            .map(|e| (e.kind.to_string(), e.path.clone()))
+            .collect();
+        assert_eq!(vec![("chain or alternative 1 gives f64, expected i32".to_string(), vec![0])], found);
+    }
+
+    #[test]
+    fn neq_str_test() {
+        let parser = take(3).chain(chain::neq_str("TER"));
+        assert!(parser.parse("END").is_ok());
+        assert!(parser.parse("TER").is_err());
+    }
+
+    #[test]
+    fn failed_parse_stores_nothing_test() {
+        let mut serial = 7_i32;
+        let mut name = String::from("CA");
+        {
+            let parser = seq(vec![
+                take(5).chain(trim().to_i32().store(&mut serial)),
+                take(4).chain(trim().store(&mut name)),
+                take(8).chain(trim().to_f64()),
+            ]);
+            assert!(parser.parse("   23 CG1 not a number").is_err());
+        }
+        assert_eq!(7, serial);
+        assert_eq!("CA", name);
+    }
+
+    #[test]
+    fn or_rollback_test() {
+        let mut first: Vec<String> = vec![];
+        {
+            let parser = or(vec![
+                seq(vec![pword().chain(push(&mut first)), tag(";")]),
+                seq(vec![pword(), tag(",")]),
+            ]);
+            assert!(parser.parse("abc,").is_ok());
+        }
+        assert!(first.is_empty());
+    }
+
+    #[test]
+    fn store_mode_test() {
+        let mut name = String::from("CA");
+        let mut note = String::from("a");
+        {
+            let parser = seq(vec![
+                take(4).chain(trim().store(&mut name)),
+                take(1).chain(chain::append(&mut note)),
+            ]);
+            assert!(parser.parse(" CG1b").is_ok());
+            assert!(parser.parse(" CG1c").is_ok());
+        }
+        assert_eq!("CG1", name);
+        assert_eq!("abc", note);
+    }
+
+    #[test]
+    fn error_span_test() {
+        let parser = seq(vec![
+            take(6).chain(trimr().eq_str("ATOM")),
+            tag("\n"),
+            skip(3),
+            take(8).chain(trim().to_f64()),
+        ]);
+        let e = parser.parse("ATOM  \n\u{e9}ab 58.8x61").unwrap_err();
+        assert_eq!(Span { offset: 10, len: 8, line: 2, column: 3 }, e.span);
+        assert_eq!(vec![3], e.path);
+    }
+
+    #[test]
+    fn validate_test() {
+        let parser = seq(vec![
+            take(0),
+            seq(vec![]),
+            skip(2).chain(trim()),
+            or(vec![
+                take(4).chain(trim().to_f64().eq_str("1.0")),
+                take(1).chain(chain::opt(trim()).trim()),
+            ]),
+        ]);
+        let errors = parser.validate().unwrap_err();
+        let found: Vec<(String, Vec<usize>)> = errors.iter()
+            .map(|e| (e.kind.to_string(), e.path.clone()))
+            .collect();
+        assert_eq!(vec![
+            ("take must be of at least one byte".to_string(), vec![0]),
+            ("sequence has no parsers".to_string(), vec![1]),
+            ("skip cannot have a chain".to_string(), vec![2]),
+            ("chain operation expected &str, found f64".to_string(), vec![3, 0]),
+            ("nothing can follow opt in a chain".to_string(), vec![3, 1]),
+        ], found);
+        assert!(take(6).chain(trimr().eq_str("ATOM")).validate().is_ok());
+    }
+
+    #[test]
+    fn validate_store_type_test() {
+        let mut name = String::new();
+        let mut serial = 0;
+        let mut bytes: Vec<u8> = vec![];
+        let parser = seq(vec![
+            take(5).chain(trim().to_i32().store(&mut name)),
+            take(4).chain(trim().store(&mut serial)),
+            take(1).chain(chain::opt(chain::to_byte().push(&mut bytes))),
+        ]);
+        let errors = parser.validate().unwrap_err();
+        let found: Vec<(String, Vec<usize>)> = errors.iter()
+            .map(|e| (e.kind.to_string(), e.path.clone()))
+            .collect();
+        assert_eq!(vec![
+            ("store target cannot hold a i32".to_string(), vec![0]),
+            ("store target cannot hold a &str".to_string(), vec![1]),
+        ], found);
+    }
+
+    #[test]
+    fn blank_test() {
+        let mut name = String::new();
+        {
+            let parser = seq(vec![
+                pword().chain(chain::store(&mut name)),
+                blank1(),
+                tag("{{"),
+                space0(),
+                line_ending(),
+                blank(),
+            ]);
+            assert_eq!(Some(""), parser.parse("use \t{{ \r\n\n").unwrap());
+        }
+        assert_eq!("use", name);
+
+        let parser = seq(vec![pword(), blank1(), tag("{{")]);
+        assert!(matches!(parser.parse("use{{").unwrap_err().kind, ParsingError::ExpectedBlank));
+        let parser = seq(vec![space1(), line_ending()]);
+        assert!(matches!(parser.parse(" x").unwrap_err().kind, ParsingError::ExpectedLineEnding));
+        assert!(matches!(parser.parse("\n").unwrap_err().kind, ParsingError::ExpectedBlank));
+    }
+
+    #[test]
+    fn until_test() {
+        let mut data = String::new();
+        {
+            let parser = seq(vec![
+                tag("{{"),
+                until(&["}}"], &["\\}}"]).chain(chain::store(&mut data)),
+                tag("}}"),
+            ]);
+            assert_eq!(Some(" end"), parser.parse("{{ é \\}} ü}} end").unwrap());
+        }
+        assert_eq!(" é \\}} ü", data);
+        assert!(until(&[], &[]).validate().is_err());
+    }
+
+    #[test]
+    fn bytes_input_test() {
+        let mut marker = String::new();
+        let frame: &[u8] = &[0x54, 0x00, 0x00, 0x00, b'C', b'O', b'R', b'D', 0xff, 0xfe, 0x01, 0x02];
+        {
+            let parser = seq(vec![
+                tag(&[0x54u8, 0x00, 0x00, 0x00]),
+                take(4).chain(chain::store(&mut marker)),
+                skip(2),
+                take(1),
+            ]);
+            assert_eq!(Some(&[0x02u8][..]), parser.parse(frame).unwrap());
+        }
+        assert_eq!("CORD", marker);
+
+        let parser = seq(vec![skip(8), take(2).chain(trim())]);
+        let error = parser.parse(frame).unwrap_err();
+        assert!(matches!(error.kind, ParsingError::ChainError(ChainingError::InvalidUtf8)));
+        assert_eq!(8, error.span.offset);
+    }
+
+    #[test]
+    fn binary_chain_test() {
+        let mut marker = 0_u32;
+        let mut x = 0_f64;
+        let mut atoms: Vec<i16> = vec![];
+        let mut raw: Vec<u8> = vec![];
+        let mut frame = vec![];
+        frame.extend_from_slice(&84_u32.to_le_bytes());
+        frame.extend_from_slice(&1.5_f64.to_be_bytes());
+        frame.extend_from_slice(&(-2_i16).to_le_bytes());
+        frame.extend_from_slice(&7_i16.to_le_bytes());
+        frame.extend_from_slice(&[0xde, 0xad]);
+        {
+            let parser = seq(vec![
+                take(4).chain(chain::le_u32().store(&mut marker)),
+                take(8).chain(chain::be_f64().store(&mut x)),
+                count(2, take(2).chain(chain::le_i16().push(&mut atoms))),
+                take(2).chain(chain::bytes().store(&mut raw)),
+            ]);
+            assert!(parser.validate().is_ok());
+            assert_eq!(Some(&[][..]), parser.parse(&frame[..]).unwrap());
+        }
+        assert_eq!(84, marker);
+        assert_eq!(1.5, x);
+        assert_eq!(vec![-2, 7], atoms);
+        assert_eq!(vec![0xde, 0xad], raw);
+
+        let parser = take(3).chain(chain::le_u32());
+        let error = parser.parse(&frame[..]).unwrap_err();
+        assert!(matches!(error.kind, ParsingError::ChainError(ChainingError::InvalidLength(4, 3))));
+
+        let mut name = String::new();
+        let parser = take(4).chain(chain::be_u32().store(&mut name));
+        assert!(parser.validate().is_err());
+    }
+
+    #[test]
+    fn parse_partial_test() {
+        let parser = seq(vec![tag("ATOM"), take(4)]);
+        assert!(matches!(parser.parse_partial("AT").unwrap_err().kind, ParsingError::Incomplete(2)));
+        assert!(matches!(parser.parse_partial("ATOM 1").unwrap_err().kind, ParsingError::Incomplete(2)));
+        assert!(matches!(parser.parse_partial("HE").unwrap_err().kind, ParsingError::TagNotEqual));
+        assert!(matches!(parser.parse("ATOM 1").unwrap_err().kind, ParsingError::InsufficientData));
+
+        // a word at the end of partial input may not be finished
+        let parser = many1(seq(vec![pword(), blank1()]));
+        assert!(parser.parse_partial("ab cd").unwrap_err().is_incomplete());
+        assert_eq!(Some(""), parser.parse("ab cd ").unwrap());
+    }
+
+    #[test]
+    fn parse_into_test() {
+        #[derive(Default)]
+        struct Atom {
+            serial: i32,
+            name: String,
+            tags: Vec<String>,
+        }
+        let parser = seq(vec![
+            take(5).chain(trim().to_i32().store_at(|a: &mut Atom| &mut a.serial)),
+            take(4).chain(trim().store_at(|a: &mut Atom| &mut a.name)),
+            many0(take(2).chain(trim().push_at(|a: &mut Atom| &mut a.tags))),
+        ]);
+        assert!(parser.validate().is_ok());
+
+        let mut first = Atom::default();
+        let mut second = Atom::default();
+        assert!(parser.parse_into("    1 N  ab", &mut first).is_ok());
+        assert!(parser.parse_into("   22 CA ", &mut second).is_ok());
+        assert_eq!((1, "N", vec!["ab".to_string()]), (first.serial, first.name.as_str(), first.tags));
+        assert_eq!((22, "CA", vec![]), (second.serial, second.name.as_str(), second.tags));
+
+        match parser.parse_into("    3 O  ", &mut 0_i32) {
+            Err(ParseError { kind: ParsingError::ChainError(ChainingError::NoOutput), .. }) => (),
+            _ => panic!("store_at needs an output it can read"),
+        }
+        assert!(parser.parse("    3 O  ").is_err());
+    }
+
+    #[test]
+    fn look_test() {
+        let mut sign = String::new();
+        let mut digits = String::new();
+        {
+            let parser = seq(vec![
+                opt(seq(vec![take(1).chain(chain::store(&mut sign)), tag("-")])),
+                opt(tag("+")),
+                not(tag("0")),
+                peek(take(2).chain(capture("first"))),
+                take(2).chain(chain::store(&mut digits)),
+            ]);
+            assert!(parser.validate().is_ok());
+
+            let (record, rest) = parser.parse_record("+12").unwrap();
+            assert_eq!(Some(""), rest);
+            assert!(record.is_empty());
+            match parser.parse("012") {
+                Err(ParseError { kind: ParsingError::UnexpectedMatch, ref path, .. }) if *path == vec![2] => (),
+                _ => panic!("not must fail where its parser matches"),
+            }
+            assert!(parser.parse("1").is_err());
+        }
+        assert_eq!(("", "12"), (sign.as_str(), digits.as_str()));
+
+        // a truncated line leaves the optional tail alone
+        let parser = seq(vec![take(2), opt(seq(vec![skip(1), take(3)]))]);
+        assert_eq!(Some(""), parser.parse("ab").unwrap());
+        assert_eq!(Some(" c"), parser.parse("ab c").unwrap());
+        assert!(parser.parse_partial("ab c").unwrap_err().is_incomplete());
+        assert!(opt(take(1)).chain(trim()).validate().is_err());
+    }
+
+    #[test]
+    fn delimited_test() {
+        let mut name = String::new();
+        let mut body = String::new();
+        {
+            let parser = seq(vec![
+                delimited(tag("<"), pword(), tag(">")).chain(chain::store(&mut name)),
+                terminated(balanced("{", "}"), tag(";")).chain(chain::store(&mut body)),
+                preceded(tag(" "), pword()),
+            ]);
+            assert!(parser.validate().is_ok());
+            assert_eq!(Some(""), parser.parse("<main>{ a { b } }; end").unwrap());
+
+            match parser.parse("<main>{ a { b };end") {
+                Err(ParseError { kind: ParsingError::InsufficientData, ref path, .. }) if *path == vec![1, 0] => (),
+                _ => panic!("an unclosed balanced fails there"),
+            }
+            assert!(parser.parse_partial("<main>{ a { b };end").unwrap_err().is_incomplete());
+        }
+        assert_eq!(("main", "{ a { b } }"), (name.as_str(), body.as_str()));
+        assert!(balanced("", "}").validate().is_err());
+    }
+
+    #[test]
+    fn capture_test() {
+        let parser = seq(vec![
+            take(3).chain(capture("kind")),
+            or(vec![
+                seq(vec![tag(" ").chain(capture("lost")), tag("x")]),
+                tag(" "),
+            ]),
+            seq(vec![
+                take(2).chain(trim().to_i32().capture("x")),
+                take(2).chain(trim().to_i32().capture("y")),
+            ]).named("point"),
+            many0(seq(vec![tag(","), pword().chain(capture("tag"))])),
+            many0(take(1).chain(capture("c"))).named("chars"),
+        ]);
+        assert!(parser.validate().is_ok());
+
+        let (record, rest) = parser.parse_record("ATM  1 2,ab,cd;;").unwrap();
+        assert_eq!(Some(""), rest);
+        assert_eq!(Some("ATM"), record.str("kind"));
+        assert_eq!(None, record.value("lost"));
+        let point = record.record("point").unwrap();
+        assert_eq!((Some(1), Some(2)), (point.i32("x"), point.i32("y")));
+        assert_eq!(Some(vec!["ab".to_string(), "cd".to_string()]), record.get("tag"));
+        assert_eq!(vec![Some(";"), Some(";")], record.records("chars").map(|r| r.str("c")).collect::<Vec<_>>());
+        assert_eq!(4, record.len());
+    }
+
+    #[test]
+    fn capture_list_test() {
+        let parser = seq(vec![
+            many0(seq(vec![tag(","), pword().chain(capture("tag"))])),
+            many0(take(1).chain(chain::eq_str(";"))).named("semis"),
+            sep_by(seq(vec![
+                pword().chain(capture("name")),
+                many0(tag("'").chain(capture("prime"))),
+            ]).named("item"), tag(" ")),
+        ]);
+        let (record, _) = parser.parse_record("").unwrap();
+        assert_eq!(Some(&[][..]), record.list("tag"));
+        assert_eq!(Some(&[][..]), record.list("semis"));
+        assert_eq!(Some(&[][..]), record.list("item"));
+
+        let (record, _) = parser.parse_record(",a;b c''").unwrap();
+        assert_eq!(Some(&[Value::Str("a".to_string())][..]), record.list("tag"));
+        assert_eq!(1, record.list("semis").unwrap().len());
+        let items: Vec<_> = record.records("item").map(|r| (r.str("name"), r.list("prime").map(|l| l.len()))).collect();
+        assert_eq!(vec![(Some("b"), Some(0)), (Some("c"), Some(2))], items);
+    }
+
+    #[test]
+    fn unnamed_seq_capture_test() {
+        let parser = seq(vec![
+            take(1).chain(capture("a")),
+            seq(vec![
+                take(1).chain(capture("b")),
+                seq(vec![take(1).chain(capture("c"))]),
+            ]),
+            seq(vec![take(1).chain(capture("d"))]).named("inner"),
+        ]);
+        let (record, _) = parser.parse_record("1234").unwrap();
+        // only the named sequence nests, the others are flattened
+        assert_eq!((Some("1"), Some("2"), Some("3")), (record.str("a"), record.str("b"), record.str("c")));
+        assert_eq!(Some("4"), record.record("inner").and_then(|r| r.str("d")));
+        assert_eq!(4, record.len());
+    }
+
+    #[test]
+    fn from_parsed_store_test() {
+        struct Serial(i32);
+        impl FromParsed for Serial {
+            fn accepts(dtype: ParsingDataTypes) -> bool {
+                i32::accepts(dtype)
+            }
+
+            fn from_parsed(value: Value) -> Option<Serial> {
+                i32::from_parsed(value).map(Serial)
+            }
+        }
+
+        let mut serial = Serial(0);
+        let mut wide = 0_i64;
+        let mut count = 0_u32;
+        let mut x: Option<f64> = None;
+        let mut chain_id = ' ';
+        {
+            let parser = seq(vec![
+                take(5).chain(trim().to_i32().store(&mut serial)),
+                take(5).chain(trim().to_i32().store(&mut wide)),
+                take(3).chain(trim().to_u8().store(&mut count)),
+                take(8).chain(trim().to_f64().store(&mut x)),
+                take(1).chain(chain::store(&mut chain_id)),
+            ]);
+            assert!(parser.validate().is_ok());
+            assert!(parser.parse("   23-4000  7  58.836A").is_ok());
+        }
+        assert_eq!((23, -4000, 7, Some(58.836), 'A'), (serial.0, wide, count, x, chain_id));
+
+        let mut small = 0_u8;
+        let parser = take(5).chain(trim().to_i32().store(&mut small));
+        match parser.parse("  300") {
+            Err(ParseError { kind: ParsingError::ChainError(ChainingError::InvalidValue(ParsingDataTypes::I32)), .. }) => (),
+            _ => panic!("300 doesn't fit a u8"),
+        }
+    }
+}
@@ -1,6 +1,5 @@
-use parser::{Parser, seq, take, skip};
-use chain::{trimr, triml, trim, empty, to_i32, to_u8, to_byte, to_f64, opt, store};
-use inspect;
+use fixed::FixedWidth;
+use parser::{Parser, ParseError};
 
 // PDB File ATOM line format
 //
@@ -22,33 +21,56 @@ use inspect;
 // 77 - 78        LString(2)    element      Element symbol, right-justified.
 // 79 - 80        LString(2)    charge       Charge  on the atom.
 
+// Many files end the line before the element, or right after z
+#[derive(FixedWidth)]
+#[col(1..=6, trimr, eq = "ATOM")]
 pub struct ATOM {
+    #[col(7..=11, i32, trim)]
     pub serial: i32,
+    #[col(13..=16, trim, write = "atom_name")]
     pub name: String,
-    pub alt_loc: u8,
+    #[col(17, trim)]
+    pub alt_loc: Option<char>,
+    #[col(18..=20)]
     pub res_name: String,
-    pub chain_id: u8,
+    #[col(22, trim)]
+    pub chain_id: Option<char>,
+    #[col(23..=26, i32, trim)]
     pub res_seq: i32,
-    pub icode: u8,
+    #[col(27, trim)]
+    pub icode: Option<char>,
+    #[col(31..=38, f64, trim, precision = 3)]
     pub x: f64,
+    #[col(39..=46, f64, trim, precision = 3)]
     pub y: f64,
+    #[col(47..=54, f64, trim, precision = 3)]
     pub z: f64,
+    #[col(55..=60, f64, trim, tail, precision = 2)]
     pub occupancy: f64,
+    #[col(61..=66, f64, trim, tail, precision = 2)]
     pub temp_factor: f64,
+    #[col(77..=78, trim, tail, right)]
     pub element: String,
+    #[col(79..=80, trim, tail)]
     pub charge: String,
 }
 
+impl Default for ATOM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ATOM {
     pub fn new() -> ATOM {
         ATOM {
             serial: 0_i32,
             name: String::with_capacity(4),
-            alt_loc: 0_u8,
+            alt_loc: None,
             res_name: String::with_capacity(3),
-            chain_id: 0_u8,
+            chain_id: None,
             res_seq: 0_i32,
-            icode: 0_u8,
+            icode: None,
             x: 0_f64,
             y: 0_f64,
             z: 0_f64,
@@ -59,38 +81,63 @@ impl ATOM {
         }
     }
 
-    pub fn parse(&mut self, buffer: &str) -> bool {
-        let mut parser = self.create_parser();
+    /// Reads one ATOM line into the record. The parser behind it is built
+    /// and validated once per thread.
+    pub fn parse(&mut self, buffer: &str) -> Result<(), ParseError> {
+        PARSER.with(|parser| parser.parse_into(buffer, self).map(|_| ()))
+    }
+}
 
-        inspect::print(&parser);
-        let result = parser.parse(buffer);
+// Names of four chars fill their columns. Shorter ones start in column
+// 14, after the blank of a one letter element, unless the element has two
+// letters.
+fn atom_name(atom: &ATOM) -> String {
+    if atom.name.len() >= 4 || atom.element.len() == 2 {
+        atom.name.clone()
+    } else {
+        format!(" {}", atom.name)
+    }
+}
 
-        match result {
-            Ok(_) => {
-                println!("Parser succeeded!");
-                true
-            }
-            Err(e) => {
-                println!("Parser failed: {:?}", e);
-                false
-            }
+thread_local! {
+    // Stores go through accessors, so one parser serves every record
+    static PARSER: Parser<'static, 'static> = {
+        let parser = ATOM::parser();
+        if let Err(errors) = parser.validate() {
+            panic!("invalid ATOM parser: {}", errors[0]);
         }
+        parser
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atom_parse_test() {
+        let mut atom = ATOM::new();
+        atom.parse("ATOM     23  CG1 VAL R   3      58.836  39.331  40.265  1.00  0.00").unwrap();
+        assert_eq!((23, "CG1", 58.836), (atom.serial, atom.name.as_str(), atom.x));
+        atom.parse("ATOM     89  OG  SER A 693      25.623   8.222  86.526  1.00 43.01           O ").unwrap();
+        assert_eq!((89, 693, "O"), (atom.serial, atom.res_seq, atom.element.as_str()));
+        assert!(atom.parse("HETATM   90").is_err());
     }
 
-    fn create_parser(&mut self) -> Parser {
-        seq(vec![    
-            take(6).chain(trimr().eq_str("ATOM")),
-            take(5).chain(trim().to_i32().store(&mut self.serial)),
-            skip(1),
-            take(4).chain(trim().store(&mut self.name)),
-            take(1).chain(opt(to_u8().store(&mut self.alt_loc))),
-            take(3).chain(store(&mut self.res_name)),
-            skip(1),
-            take(1).chain(to_byte().store(&mut self.icode)),
-            skip(8),
-            take(8).chain(trim().to_f64().store(&mut self.x)),
-            take(8).chain(trim().to_f64().store(&mut self.y)),
-            take(8).chain(trim().to_f64().store(&mut self.z)),
-        ])
+    #[test]
+    fn to_fixed_test() {
+        let test4 = "ATOM     23  CG1 VAL R   3      58.836  39.331  40.265  1.00  0.00";
+        let mut atom = ATOM::new();
+        atom.parse(test4).unwrap();
+        assert_eq!((None, Some('R'), None), (atom.alt_loc, atom.chain_id, atom.icode));
+        assert_eq!(test4, atom.to_fixed().unwrap());
+
+        let test3 = "ATOM     90 2HE2 GLN R   7      55.573  32.236  40.457  1.00  0.00           H";
+        atom.parse(test3).unwrap();
+        assert_eq!(test3, atom.to_fixed().unwrap());
+
+        let written = ATOM::new().to_fixed().unwrap();
+        assert!(!written.contains('\0'));
+        assert_eq!(" ", &written[16..17]);
     }
-}
\ No newline at end of file
+}
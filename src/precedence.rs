@@ -0,0 +1,456 @@
+use std::cell::RefCell;
+use grammar::{Grammar, DEFAULT_DEPTH_LIMIT};
+use parser::{self, Parser, ParseError, blank0};
+
+/// How an infix operator groups with others of its precedence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    // a - b - c is (a - b) - c
+    Left,
+    // a ^ b ^ c is a ^ (b ^ c)
+    Right,
+}
+
+type AtomFold<'c, T> = Box<dyn Fn(&str) -> T + 'c>;
+type UnaryFold<'c, T> = Box<dyn Fn(&str, T) -> T + 'c>;
+type BinaryFold<'c, T> = Box<dyn Fn(&str, T, T) -> T + 'c>;
+
+/// The parsers of an expression and how they bind, everything but the
+/// folds. Climbed by the parser runner.
+pub(crate) struct Operators<'c, 't> {
+    pub(crate) prefixes: Vec<(Parser<'c, 't>, u32)>,
+    pub(crate) groups: Vec<(Parser<'c, 't>, Parser<'c, 't>)>,
+    pub(crate) atoms: Vec<Parser<'c, 't>>,
+    pub(crate) infixes: Vec<(Parser<'c, 't>, u32, Assoc)>,
+    pub(crate) postfixes: Vec<(Parser<'c, 't>, u32)>,
+    pub(crate) blank: Parser<'c, 't>,
+    pub(crate) depth_limit: usize,
+}
+
+/// Kinds of parsers of an expression, in the order they are children
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Part {
+    Prefix,
+    Open,
+    Close,
+    Atom,
+    Infix,
+    Postfix,
+    Blank,
+}
+
+impl<'c, 't> Operators<'c, 't> {
+    pub(crate) fn children(&self) -> Vec<&Parser<'c, 't>> {
+        let mut children: Vec<&Parser<'c, 't>> = self.prefixes.iter().map(|op| &op.0).collect();
+        for group in self.groups.iter() {
+            children.push(&group.0);
+            children.push(&group.1);
+        }
+        children.extend(self.atoms.iter());
+        children.extend(self.infixes.iter().map(|op| &op.0));
+        children.extend(self.postfixes.iter().map(|op| &op.0));
+        children.push(&self.blank);
+        children
+    }
+
+    /// Index of the `i`th parser of `part` among the children, as used in
+    /// error paths
+    pub(crate) fn index(&self, part: Part, i: usize) -> usize {
+        let groups = self.prefixes.len();
+        let atoms = groups + 2 * self.groups.len();
+        let infixes = atoms + self.atoms.len();
+        let postfixes = infixes + self.infixes.len();
+        match part {
+            Part::Prefix => i,
+            Part::Open => groups + 2 * i,
+            Part::Close => groups + 2 * i + 1,
+            Part::Atom => atoms + i,
+            Part::Infix => infixes + i,
+            Part::Postfix => postfixes + i,
+            Part::Blank => postfixes + self.postfixes.len(),
+        }
+    }
+}
+
+/// What an expression matched, by the index of each parser and the span
+/// of its text, counted in bytes from the start of the expression
+#[derive(Debug, Clone)]
+pub(crate) enum Climbed {
+    Atom(usize, (usize, usize)),
+    Prefix(usize, (usize, usize), Box<Climbed>),
+    Infix(usize, (usize, usize), Box<Climbed>, Box<Climbed>),
+    Postfix(usize, (usize, usize), Box<Climbed>),
+}
+
+impl Climbed {
+    // Every span starts and ends on a char of `text`
+    pub(crate) fn on_chars(&self, text: &str) -> bool {
+        let on = |(start, end): (usize, usize)| text.is_char_boundary(start) && text.is_char_boundary(end);
+        match *self {
+            Climbed::Atom(_, span) => on(span),
+            Climbed::Prefix(_, span, ref operand) | Climbed::Postfix(_, span, ref operand) => on(span) && operand.on_chars(text),
+            Climbed::Infix(_, span, ref left, ref right) => on(span) && left.on_chars(text) && right.on_chars(text),
+        }
+    }
+}
+
+/// Folds what an expression matched and writes the tree to its target,
+/// when the parse commits
+pub(crate) trait Build {
+    fn build(&self, climbed: &Climbed, text: &str);
+}
+
+/// A `Precedence` made into a parser by `Precedence::parser`
+pub struct Expression<'c, 't> {
+    pub(crate) operators: Operators<'c, 't>,
+    pub(crate) build: Box<dyn Build + 'c>,
+}
+
+struct Folds<'c, T> {
+    prefixes: Vec<UnaryFold<'c, T>>,
+    atoms: Vec<AtomFold<'c, T>>,
+    infixes: Vec<BinaryFold<'c, T>>,
+    postfixes: Vec<UnaryFold<'c, T>>,
+}
+
+impl<'c, T> Folds<'c, T> {
+    fn fold(&self, climbed: &Climbed, text: &str) -> T {
+        match *climbed {
+            Climbed::Atom(i, (start, end)) => (self.atoms[i])(&text[start..end]),
+            Climbed::Prefix(i, (start, end), ref operand) => {
+                (self.prefixes[i])(&text[start..end], self.fold(operand, text))
+            }
+            Climbed::Infix(i, (start, end), ref left, ref right) => {
+                let left = self.fold(left, text);
+                (self.infixes[i])(&text[start..end], left, self.fold(right, text))
+            }
+            Climbed::Postfix(i, (start, end), ref operand) => {
+                (self.postfixes[i])(&text[start..end], self.fold(operand, text))
+            }
+        }
+    }
+}
+
+// The folds of a parser and where the tree goes
+struct Target<'c, T> {
+    folds: Folds<'c, T>,
+    tree: RefCell<&'c mut Option<T>>,
+}
+
+impl<'c, T> Build for Target<'c, T> {
+    fn build(&self, climbed: &Climbed, text: &str) {
+        **self.tree.borrow_mut() = Some(self.folds.fold(climbed, text));
+    }
+}
+
+/// Expressions of operands and operators, built into a `T` by folds.
+/// Atoms, operators and the blanks between them are plain parsers, the
+/// text each one matched goes to its fold:
+///
+/// ```text
+/// let mut tree = None;
+/// let select = seq(vec![
+///     tag("select "),
+///     Precedence::new()
+///         .atom(pword(), |name| Expr::Field(name.to_string()))
+///         .infix(tag(">="), 4, Assoc::Left, |_, l, r| Expr::Ge(l, r))
+///         .infix(pword().chain(eq_str("and")), 2, Assoc::Left, |_, l, r| Expr::And(l, r))
+///         .prefix(pword().chain(eq_str("not")), 3, |_, e| Expr::Not(e))
+///         .group(tag("("), tag(")"))
+///         .parser(&mut tree),
+/// ]);
+/// ```
+///
+/// Higher precedences bind tighter. When several operators match at the
+/// same place the longest match wins, ties go to the one added first, so
+/// `>=` and `>` can be added in any order. Operands try prefixes, then
+/// groups, then atoms the same way. Operators and prefixes that match
+/// nothing are passed over.
+///
+/// `parser` makes it a parser like any other, for any input, inside
+/// sequences, ors, repetitions and grammar rules. The stores and captures
+/// of the parsers that make up the expression are kept, and the tree is
+/// written when the parse succeeds. `parse` runs it on its own text and
+/// drops them.
+pub struct Precedence<'c, 't, T> {
+    operators: Operators<'c, 't>,
+    folds: Folds<'c, T>,
+}
+
+impl<'c, 't, T> Default for Precedence<'c, 't, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c, 't, T> Precedence<'c, 't, T> {
+    pub fn new() -> Precedence<'c, 't, T> {
+        Precedence {
+            operators: Operators {
+                prefixes: vec![],
+                groups: vec![],
+                atoms: vec![],
+                infixes: vec![],
+                postfixes: vec![],
+                blank: blank0(),
+                depth_limit: DEFAULT_DEPTH_LIMIT,
+            },
+            folds: Folds { prefixes: vec![], atoms: vec![], infixes: vec![], postfixes: vec![] },
+        }
+    }
+
+    /// An operand, `fold` makes it into a tree from its text
+    pub fn atom<F: Fn(&str) -> T + 'c>(mut self, parser: Parser<'c, 't>, fold: F) -> Self {
+        self.operators.atoms.push(parser);
+        self.folds.atoms.push(Box::new(fold));
+        self
+    }
+
+    /// An operator before its operand, which takes in every operator
+    /// binding at least as tight as `precedence`
+    pub fn prefix<F: Fn(&str, T) -> T + 'c>(mut self, parser: Parser<'c, 't>, precedence: u32, fold: F) -> Self {
+        self.operators.prefixes.push((parser, precedence));
+        self.folds.prefixes.push(Box::new(fold));
+        self
+    }
+
+    pub fn infix<F: Fn(&str, T, T) -> T + 'c>(mut self,
+                                              parser: Parser<'c, 't>,
+                                              precedence: u32,
+                                              assoc: Assoc,
+                                              fold: F)
+                                              -> Self {
+        self.operators.infixes.push((parser, precedence, assoc));
+        self.folds.infixes.push(Box::new(fold));
+        self
+    }
+
+    pub fn postfix<F: Fn(&str, T) -> T + 'c>(mut self, parser: Parser<'c, 't>, precedence: u32, fold: F) -> Self {
+        self.operators.postfixes.push((parser, precedence));
+        self.folds.postfixes.push(Box::new(fold));
+        self
+    }
+
+    /// A whole expression between `open` and `close` is an operand
+    pub fn group(mut self, open: Parser<'c, 't>, close: Parser<'c, 't>) -> Self {
+        self.operators.groups.push((open, close));
+        self
+    }
+
+    /// What may come between operands and operators, `blank0()` unless set
+    pub fn blank(mut self, parser: Parser<'c, 't>) -> Self {
+        self.operators.blank = parser;
+        self
+    }
+
+    /// Operators and groups nested deeper than this fail with
+    /// `ParsingError::TooDeep`, counted like the rules of a grammar
+    pub fn depth(mut self, limit: usize) -> Self {
+        self.operators.depth_limit = limit;
+        self
+    }
+
+    /// A parser of the longest expression at its place, the tree goes into
+    /// `tree` when the whole parse succeeds. Blanks after the expression
+    /// are left to the parsers after it.
+    pub fn parser(self, tree: &'c mut Option<T>) -> Parser<'c, 't> where T: 'c {
+        let build = Target { folds: self.folds, tree: RefCell::new(tree) };
+        parser::expression(Expression { operators: self.operators, build: Box::new(build) })
+    }
+
+    /// Parses the longest expression at the start of `buffer`. Blanks
+    /// after it are left in the rest.
+    pub fn parse<'d>(&self, buffer: &'d str) -> Result<(T, &'d str), ParseError> {
+        self.parse_with(buffer, None)
+    }
+
+    /// Like `parse`, `rule` parsers use the rules of `grammar`
+    pub fn parse_in<'d>(&self, grammar: &Grammar<'c, 't>, buffer: &'d str) -> Result<(T, &'d str), ParseError> {
+        self.parse_with(buffer, Some(grammar))
+    }
+
+    fn parse_with<'d>(&self, buffer: &'d str, grammar: Option<&Grammar<'c, 't>>) -> Result<(T, &'d str), ParseError> {
+        let (climbed, end) = parser::climb(&self.operators, buffer, grammar)?;
+        Ok((self.folds.fold(&climbed, &buffer[..end]), &buffer[end..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain::{eq_str, neq_str, push};
+    use parser::{ParsingError, pword, tag, take, until, delimited, opt, or, seq, rule};
+
+    fn arithmetic<'c>() -> Precedence<'c, 'static, i64> {
+        Precedence::<i64>::new()
+            .atom(until(&[" ", "+", "-", "*", "^", "!", "(", ")"], &[]).chain(neq_str("")), |n| n.parse().unwrap())
+            .infix(tag("+"), 1, Assoc::Left, |_, l, r| l + r)
+            .infix(tag("-"), 1, Assoc::Left, |_, l, r| l - r)
+            .infix(tag("*"), 2, Assoc::Left, |_, l, r| l * r)
+            .infix(tag("^"), 4, Assoc::Right, |_, l, r| l.pow(r as u32))
+            .prefix(tag("-"), 3, |_, e| -e)
+            .postfix(tag("!"), 5, |_, e| (1..=e).product())
+            .group(tag("("), tag(")"))
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        let expr = arithmetic();
+        assert_eq!((7, ""), expr.parse("1 + 2 * 3").unwrap());
+        assert_eq!((9, ""), expr.parse("(1 + 2) * 3").unwrap());
+        assert_eq!((-4, ""), expr.parse("1 - 2 - 3").unwrap());
+        assert_eq!((512, ""), expr.parse("2 ^ 3 ^ 2").unwrap());
+        assert_eq!((-9, ""), expr.parse("-3^2").unwrap());
+        assert_eq!((-7, " )"), expr.parse("-3! - 1 )").unwrap());
+
+        let e = expr.parse("1 + (2 * ").unwrap_err();
+        assert_eq!((9, 1, 10), (e.span.offset, e.span.line, e.span.column));
+        // an open group fails where its close should be
+        assert_eq!(2, expr.parse("(2").unwrap_err().span.offset);
+        let deep = format!("{}1{}", "(".repeat(20), ")".repeat(20));
+        assert_eq!((1, ""), expr.parse(deep.as_str()).unwrap());
+        match arithmetic().depth(10).parse(deep.as_str()) {
+            Err(ParseError { kind: ParsingError::TooDeep(10), span, .. }) => assert_eq!(12, span.column),
+            other => panic!("groups past the limit must fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn depth_test() {
+        // a grammar and an expression nested as deep as the limit pass,
+        // one level more fails in both
+        let parens = Grammar::new()
+            .define("p", or(vec![seq(vec![tag("("), rule("p"), tag(")")]), tag("1")]))
+            .depth(3);
+        let expr = arithmetic().depth(3);
+        for (n, ok) in [(3, true), (4, false)] {
+            let input = format!("{}1{}", "(".repeat(n), ")".repeat(n));
+            assert_eq!(ok, parens.parse("p", input.as_str()).is_ok());
+            assert_eq!(ok, expr.parse(input.as_str()).is_ok());
+        }
+    }
+
+    #[test]
+    fn rule_atom_test() {
+        let grammar = Grammar::new().define("number", until(&[" ", "+"], &[]).chain(neq_str("")));
+        let expr = Precedence::<i64>::new()
+            .atom(rule("number"), |n| n.parse().unwrap())
+            .infix(tag("+"), 1, Assoc::Left, |_, l, r| l + r);
+        assert_eq!((5, ""), expr.parse_in(&grammar, "2 + 3").unwrap());
+        match expr.parse("2 + 3") {
+            Err(ParseError { kind: ParsingError::UnknownRule(ref name), .. }) if name == "number" => (),
+            other => panic!("rules need a grammar, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_operator_test() {
+        // operators that match nothing are passed over instead of being
+        // applied forever
+        let primes = Precedence::<i64>::new()
+            .atom(take(1).chain(neq_str(" ")), |n| n.parse().unwrap())
+            .postfix(opt(tag("'")), 5, |_, e| e + 1)
+            .infix(opt(tag("+")), 1, Assoc::Left, |_, l, r| l + r);
+        assert_eq!((1, " 2"), primes.parse("1 2").unwrap());
+        assert_eq!((7, " 2"), primes.parse("1'' + 3' 2").unwrap());
+    }
+
+    #[test]
+    fn parser_test() {
+        let mut tree = None;
+        let mut fields: Vec<String> = vec![];
+        {
+            let parser = seq(vec![
+                tag("if "),
+                Precedence::new()
+                    .atom(pword().chain(push(&mut fields)), |w| Select::Field(w.to_string()))
+                    .infix(pword().chain(eq_str("and").capture("op")), 2, Assoc::Left, |_, l, r| {
+                        Select::And(Box::new(l), Box::new(r))
+                    })
+                    .prefix(pword().chain(eq_str("not")), 3, |_, e| Select::Not(Box::new(e)))
+                    .parser(&mut tree),
+                tag(";"),
+            ]);
+            assert!(parser.validate().is_ok());
+            assert!(parser.parse("if a and;").is_err());
+            let (record, rest) = parser.parse_record("if a and not b;x".as_bytes()).unwrap();
+            assert_eq!(Some("x".as_bytes()), rest);
+            assert_eq!(Some("and"), record.str("op"));
+        }
+        // the failed parse left nothing behind
+        assert_eq!(vec!["a", "b"], fields);
+        let field = |f: &str| Box::new(Select::Field(f.to_string()));
+        assert_eq!(Some(Select::And(field("a"), Box::new(Select::Not(field("b"))))), tree);
+    }
+
+    #[test]
+    fn rule_test() {
+        let mut sum = None;
+        {
+            let grammar = Grammar::new()
+                .define("sum", Precedence::<i64>::new()
+                    .atom(rule("number"), |n| n.parse().unwrap())
+                    .infix(tag("+"), 1, Assoc::Left, |_, l, r| l + r)
+                    .group(tag("("), tag(")"))
+                    .parser(&mut sum))
+                .define("number", until(&[" ", "+", "(", ")"], &[]).chain(neq_str("").capture("n")));
+            assert!(grammar.validate().is_ok());
+            let (record, rest) = grammar.parse_record("sum", "1 + (2 + 3)").unwrap();
+            assert_eq!(Some(""), rest);
+            assert_eq!(Some("3"), record.str("n"));
+        }
+        assert_eq!(Some(6), sum);
+    }
+
+    #[test]
+    fn char_boundary_test() {
+        let expr = Precedence::<String>::new()
+            .atom(take(1), |c| c.to_string())
+            .infix(tag("+"), 1, Assoc::Left, |_, l, r| l + &r);
+        assert_eq!(("ab".to_string(), ""), expr.parse("a+b").unwrap());
+        // one byte of `é` isn't a character
+        assert!(expr.parse("a+é").is_err());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Select {
+        Field(String),
+        Int(i32),
+        Str(String),
+        Cmp(String, Box<Select>, Box<Select>),
+        And(Box<Select>, Box<Select>),
+        Or(Box<Select>, Box<Select>),
+        Not(Box<Select>),
+    }
+
+    #[test]
+    fn selection_test() {
+        let word = |w: &'static str| pword().chain(eq_str(w));
+        let cmp = |op: &str, l, r| Select::Cmp(op.to_string(), Box::new(l), Box::new(r));
+        let expr = Precedence::new()
+            .atom(pword(), |w| Select::Field(w.to_string()))
+            .atom(delimited(tag("'"), until(&["'"], &[]), tag("'")), |s| Select::Str(s[1..s.len() - 1].to_string()))
+            .atom(until(&[" ", "(", ")"], &[]).chain(neq_str("")), |n| Select::Int(n.parse().unwrap()))
+            .infix(tag(">"), 4, Assoc::Left, cmp)
+            .infix(tag(">="), 4, Assoc::Left, cmp)
+            .infix(tag("=="), 4, Assoc::Left, cmp)
+            .prefix(word("not"), 3, |_, e| Select::Not(Box::new(e)))
+            .infix(word("and"), 2, Assoc::Left, |_, l, r| Select::And(Box::new(l), Box::new(r)))
+            .infix(word("or"), 1, Assoc::Left, |_, l, r| Select::Or(Box::new(l), Box::new(r)))
+            .group(tag("("), tag(")"));
+
+        let field = |f: &str| Select::Field(f.to_string());
+        let (tree, rest) = expr.parse("resSeq >= 10 and chain == 'A'").unwrap();
+        assert_eq!("", rest);
+        assert_eq!(Select::And(
+            Box::new(cmp(">=", field("resSeq"), Select::Int(10))),
+            Box::new(cmp("==", field("chain"), Select::Str("A".to_string()))),
+        ), tree);
+
+        let (tree, _) = expr.parse("not notable or (x > 1)").unwrap();
+        assert_eq!(Select::Or(
+            Box::new(Select::Not(Box::new(field("notable")))),
+            Box::new(cmp(">", field("x"), Select::Int(1))),
+        ), tree);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::hash_map;
+use std::collections::HashMap;
+use value::{Value, FromParsed};
+
+/// What the `capture` operations of a parse produced, by name.
+///
+/// Captures made inside a named parser go into a nested record, and
+/// captures repeated by a repetition are collected into a list, empty
+/// when it matched nothing. Sequences
+/// and ors without a name don't nest, their captures are flattened into
+/// the closest named parser, or the root.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Record {
+    fields: HashMap<String, Value>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record { fields: HashMap::new() }
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+
+    /// The value converted like a store would, None if it is missing or
+    /// doesn't fit a T
+    pub fn get<T: FromParsed>(&self, name: &str) -> Option<T> {
+        match self.fields.get(name) {
+            Some(value) => T::from_parsed(value.clone()),
+            None => None,
+        }
+    }
+
+    pub fn str(&self, name: &str) -> Option<&str> {
+        match self.fields.get(name) {
+            Some(Value::Str(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn i32(&self, name: &str) -> Option<i32> {
+        self.get(name)
+    }
+
+    pub fn f64(&self, name: &str) -> Option<f64> {
+        self.get(name)
+    }
+
+    pub fn record(&self, name: &str) -> Option<&Record> {
+        match self.fields.get(name) {
+            Some(Value::Record(r)) => Some(r),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self, name: &str) -> Option<&[Value]> {
+        match self.fields.get(name) {
+            Some(Value::List(l)) => Some(l),
+            _ => None,
+        }
+    }
+
+    /// The records of a list, skipping anything else in it
+    pub fn records<'r>(&'r self, name: &str) -> impl Iterator<Item = &'r Record> {
+        self.list(name).unwrap_or(&[]).iter().filter_map(|v| match *v {
+            Value::Record(ref r) => Some(r),
+            _ => None,
+        })
+    }
+
+    pub fn insert(&mut self, name: &str, value: Value) {
+        self.fields.insert(name.to_string(), value);
+    }
+
+    /// Adds to the list under `name`, a value already there becomes the
+    /// first element
+    pub fn push(&mut self, name: &str, value: Value) {
+        let slot = self.fields.entry(name.to_string()).or_insert_with(|| Value::List(vec![]));
+        if let Value::List(ref mut list) = *slot {
+            list.push(value);
+            return;
+        }
+        let first = ::std::mem::replace(slot, Value::List(vec![]));
+        *slot = Value::List(vec![first, value]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, Value> {
+        self.fields.iter()
+    }
+}
+
+impl IntoIterator for Record {
+    type Item = (String, Value);
+    type IntoIter = hash_map::IntoIter<String, Value>;
+
+    fn into_iter(self) -> hash_map::IntoIter<String, Value> {
+        self.fields.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn getters_test() {
+        let mut inner = Record::new();
+        inner.insert("x", Value::F64(1.5));
+        let mut record = Record::new();
+        record.insert("name", Value::Str("CA".to_string()));
+        record.insert("serial", Value::I32(7));
+        record.push("atoms", Value::Record(inner.clone()));
+        record.push("atoms", Value::Record(inner));
+        record.push("tags", Value::U8(1));
+        record.push("tags", Value::U8(2));
+
+        assert_eq!(Some("CA"), record.str("name"));
+        assert_eq!(Some(7), record.i32("serial"));
+        assert_eq!(Some(7_u64), record.get("serial"));
+        assert_eq!(None, record.f64("serial"));
+        assert_eq!(Some(vec![1_u16, 2]), record.get("tags"));
+        assert_eq!(vec![Some(1.5), Some(1.5)], record.records("atoms").map(|r| r.f64("x")).collect::<Vec<_>>());
+        assert_eq!(None, record.record("missing"));
+    }
+}
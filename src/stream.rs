@@ -0,0 +1,278 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use parser::{Parser, ParseError};
+
+#[derive(Debug)]
+pub enum StreamError {
+    // Reading from the source failed
+    Io(io::Error),
+    // The parser failed, the span is measured from the start of the stream
+    Parse(ParseError),
+    // The parser matched nothing at this offset of the stream, running it
+    // again would never get past it
+    EmptyMatch(usize),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamError::Io(ref e) => write!(f, "read failed: {}", e),
+            StreamError::Parse(ref e) => write!(f, "{}", e),
+            StreamError::EmptyMatch(offset) => write!(f, "parser matched nothing at offset {}", offset),
+        }
+    }
+}
+
+impl Error for StreamError {}
+
+/// Feeds a parser from a `BufRead` without ever holding the whole input.
+/// Only what the current record needs is kept in memory.
+///
+/// Stores work as usual, so a parser that is run once per record should
+/// `push` its values, or be built again for each record.
+pub struct Stream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    // Bytes of `buffer` already parsed
+    start: usize,
+    // Bytes dropped from the front of `buffer`, plus `start`
+    consumed: usize,
+    // Line endings in everything consumed
+    lines: usize,
+    // Chars consumed since the last line ending
+    column: usize,
+    eof: bool,
+}
+
+impl<R: BufRead> Stream<R> {
+    pub fn new(reader: R) -> Stream<R> {
+        Stream {
+            reader,
+            buffer: vec![],
+            start: 0,
+            consumed: 0,
+            lines: 0,
+            column: 0,
+            eof: false,
+        }
+    }
+
+    /// Runs `parser` once on the front of the stream, reading more while it
+    /// is incomplete. False once the stream is exhausted. A parser that
+    /// matches nothing while data is left fails with `EmptyMatch`.
+    pub fn next(&mut self, parser: &Parser) -> Result<bool, StreamError> {
+        loop {
+            if self.start == self.buffer.len() && !self.fill()? {
+                return Ok(false);
+            }
+
+            let result = {
+                let pending = &self.buffer[self.start..];
+                let result = if self.eof {
+                    parser.parse(pending)
+                } else {
+                    parser.parse_partial(pending)
+                };
+                result.map(|rest| pending.len() - rest.map_or(0, |r| r.len()))
+            };
+            match result {
+                Ok(0) => return Err(StreamError::EmptyMatch(self.consumed)),
+                Ok(used) => {
+                    self.advance(used);
+                    return Ok(true);
+                }
+                Err(ref e) if e.is_incomplete() => {
+                    self.fill()?;
+                }
+                Err(mut e) => {
+                    e.shift(self.consumed, self.lines, self.column);
+                    return Err(StreamError::Parse(e));
+                }
+            }
+        }
+    }
+
+    /// Runs `parser` on the next line, without its `\n` or `\r\n`. The
+    /// parser doesn't have to use the whole line. False once the stream
+    /// is exhausted.
+    pub fn next_line(&mut self, parser: &Parser) -> Result<bool, StreamError> {
+        // Bytes after `start` already known to hold no line ending, kept
+        // relative as fill moves the buffer
+        let mut scanned = 0;
+        let end = loop {
+            let from = self.start + scanned;
+            if let Some(i) = self.buffer[from..].iter().position(|&b| b == b'\n') {
+                break from + i + 1;
+            }
+            scanned = self.buffer.len() - self.start;
+            // the last line doesn't need a line ending
+            if !self.fill()? {
+                break self.buffer.len();
+            }
+        };
+        if end == self.start {
+            return Ok(false);
+        }
+
+        let result = {
+            let mut line = &self.buffer[self.start..end];
+            if line.ends_with(b"\n") {
+                line = &line[..line.len() - 1];
+            }
+            if line.ends_with(b"\r") {
+                line = &line[..line.len() - 1];
+            }
+            parser.parse(line)
+        };
+        match result {
+            Ok(_) => {
+                self.advance(end - self.start);
+                Ok(true)
+            }
+            Err(mut e) => {
+                e.shift(self.consumed, self.lines, self.column);
+                Err(StreamError::Parse(e))
+            }
+        }
+    }
+
+    /// Runs `parser` on every line left, returns how many there were
+    pub fn lines(&mut self, parser: &Parser) -> Result<usize, StreamError> {
+        let mut count = 0;
+        while self.next_line(parser)? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Bytes parsed so far
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    fn advance(&mut self, used: usize) {
+        let parsed = &self.buffer[self.start..self.start + used];
+        self.lines += parsed.iter().filter(|&&b| b == b'\n').count();
+        // utf8 continuation bytes don't start a char
+        let chars = |bytes: &[u8]| bytes.iter().filter(|&&b| b & 0xC0 != 0x80).count();
+        self.column = match parsed.iter().rposition(|&b| b == b'\n') {
+            Some(i) => chars(&parsed[i + 1..]),
+            None => self.column + chars(parsed),
+        };
+        self.consumed += used;
+        self.start += used;
+    }
+
+    // Reads another chunk into the buffer, false at the end of the source
+    fn fill(&mut self) -> Result<bool, StreamError> {
+        if self.eof {
+            return Ok(false);
+        }
+        // Parsed bytes are dropped before growing the buffer
+        self.buffer.drain(..self.start);
+        self.start = 0;
+
+        let read = {
+            let chunk = loop {
+                match self.reader.fill_buf() {
+                    Ok(chunk) => break chunk,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(StreamError::Io(e)),
+                }
+            };
+            self.buffer.extend_from_slice(chunk);
+            chunk.len()
+        };
+        self.reader.consume(read);
+        if read == 0 {
+            self.eof = true;
+        }
+        Ok(read > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use parser::{seq, tag, take, until, many0, blank1, line_ending};
+    use chain::{trim, to_i32};
+
+    #[test]
+    fn next_test() {
+        let input = "ATOM 1\nATOM   22\nATOM 333\n";
+        let mut serials: Vec<i32> = vec![];
+        {
+            let parser = seq(vec![
+                tag("ATOM"),
+                blank1(),
+                until(&["\n"], &[]).chain(to_i32().push(&mut serials)),
+                line_ending(),
+            ]);
+            // A tiny buffer makes every record span several reads
+            let mut stream = Stream::new(BufReader::with_capacity(3, input.as_bytes()));
+            let mut count = 0;
+            while stream.next(&parser).unwrap() {
+                count += 1;
+            }
+            assert_eq!(3, count);
+            assert_eq!(input.len(), stream.consumed());
+        }
+        assert_eq!(vec![1, 22, 333], serials);
+    }
+
+    #[test]
+    fn lines_test() {
+        let input = "ATOM      1\r\nATOM     22\nHETATM   33\n";
+        let mut serials: Vec<i32> = vec![];
+        {
+            let parser = seq(vec![
+                take(6).chain(trim().eq_str("ATOM")),
+                take(5).chain(trim().to_i32().push(&mut serials)),
+            ]);
+            let mut stream = Stream::new(BufReader::with_capacity(4, input.as_bytes()));
+            match stream.lines(&parser) {
+                Err(StreamError::Parse(e)) => {
+                    assert_eq!(3, e.span.line);
+                    assert_eq!(input.find("HETATM").unwrap(), e.span.offset);
+                }
+                _ => panic!("third line should fail"),
+            }
+        }
+        assert_eq!(vec![1, 22], serials);
+    }
+
+    #[test]
+    fn next_error_test() {
+        let parser = seq(vec![tag("ab"), tag("c")]);
+        let mut stream = Stream::new(BufReader::new("abcabX\nabc".as_bytes()));
+        assert!(stream.next(&parser).unwrap());
+        match stream.next(&parser) {
+            Err(StreamError::Parse(e)) => assert_eq!((5, 1, 6), (e.span.offset, e.span.line, e.span.column)),
+            other => panic!("second record should fail, got {:?}", other),
+        }
+
+        let parser = seq(vec![tag("ab"), line_ending()]);
+        let mut stream = Stream::new(BufReader::new("ab\nab\nX".as_bytes()));
+        assert!(stream.next(&parser).unwrap());
+        assert!(stream.next(&parser).unwrap());
+        match stream.next(&parser) {
+            Err(StreamError::Parse(e)) => assert_eq!((6, 3, 1), (e.span.offset, e.span.line, e.span.column)),
+            other => panic!("third record should fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_match_test() {
+        let parser = many0(tag("x"));
+        let mut stream = Stream::new(BufReader::with_capacity(2, "xxxab".as_bytes()));
+        assert!(stream.next(&parser).unwrap());
+        match stream.next(&parser) {
+            Err(StreamError::EmptyMatch(3)) => (),
+            other => panic!("a parser stuck on the input must fail, got {:?}", other),
+        }
+        let mut stream = Stream::new(BufReader::new("".as_bytes()));
+        assert!(!stream.next(&parser).unwrap());
+    }
+}
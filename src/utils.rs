@@ -1,3 +1,5 @@
+use input::Input;
+
 #[derive(Debug)]
 pub enum Error {
     InsufficientBuffer,
@@ -6,42 +8,41 @@ pub enum Error {
     TagNotEqual,
     InvalidPWord,
     TrimingError,
+    ExpectedBlank,
+    ExpectedLineEnding,
 }
 
 #[derive(Debug)]
-pub struct Split<'s> {
-    pub left: &'s str,
-    pub right: &'s str,
+pub struct Split<I> {
+    pub left: I,
+    pub right: I,
 }
 
-impl<'s> Split<'s> {
-    pub fn new(left: &'s str, right: &'s str) -> Split<'s> {
-        Split { left: left, right: right }
+impl<I> Split<I> {
+    pub fn new(left: I, right: I) -> Split<I> {
+        Split { left, right }
     }
 }
 
-pub fn tag<'b, 'v>(buffer: &'b str, value: &'v str) -> Result<Split<'b>, Error> {
+/// `value` can be text or bytes, whatever the buffer is
+pub fn tag<'d, I: Input<'d>, T: AsRef<[u8]> + ?Sized>(buffer: I, value: &T) -> Result<Split<I>, Error> {
+    let value = value.as_ref();
     let rtake = take(buffer, value.len());
 
     match rtake {
         Err(e) => Err(e),
-        Ok(Split {left, right}) if left == value => Ok(Split::new(left, right)),
+        Ok(Split {left, right}) if left.as_bytes() == value => Ok(Split::new(left, right)),
         Ok(_) => Err(Error::TagNotEqual),
     }
 }
 
 #[inline]
-pub fn take(buffer: &str, count : usize) -> Result<Split, Error> {
-    if buffer.len() < count {
-        return Err(Error::InsufficientBuffer);
-    } else if !buffer.is_char_boundary(count) {
-        return Err(Error::InvalidCharBoundary);
-    }
-    Ok(Split::new(&buffer[..count], &buffer[count..]))
+pub fn take<'d, I: Input<'d>>(buffer: I, count : usize) -> Result<Split<I>, Error> {
+    buffer.split_at_byte(count)
 }
 
 #[inline]
-pub fn skip(buffer: &str, count : usize) -> Result<Split, Error> {
+pub fn skip<'d, I: Input<'d>>(buffer: I, count : usize) -> Result<Split<I>, Error> {
     take(buffer, count)
 }
 
@@ -49,14 +50,14 @@ pub fn skip(buffer: &str, count : usize) -> Result<Split, Error> {
 /// Tests if byte is ASCII alphabetic: A-Z, a-z
 #[inline]
 pub fn is_alphabetic(chr:u8) -> bool {
-  (chr >= 0x41 && chr <= 0x5A) || (chr >= 0x61 && chr <= 0x7A)
+  (0x41..=0x5A).contains(&chr) || (0x61..=0x7A).contains(&chr)
 }
 
 // Stolen from NOM
 /// Tests if byte is ASCII digit: 0-9
 #[inline]
 pub fn is_digit(chr: u8) -> bool {
-  chr >= 0x30 && chr <= 0x39
+  (0x30..=0x39).contains(&chr)
 }
 
 #[inline]
@@ -64,15 +65,20 @@ pub fn is_blank(chr: char) -> bool {
     chr == ' ' || chr == '\t' || chr == '\r' || chr == '\n'
 }
 
+#[inline]
+pub fn is_space(chr: char) -> bool {
+    chr == ' ' || chr == '\t'
+}
+
 /// Captures characters until a no pword char is found
 /// Note pword starts with A-Z or a-z or `_` and can have A-Z, a-z, `_`
 /// and 0-9 after the first char
-pub fn pword<'w>(buffer: &'w str) -> Result<Split, Error> {
+pub fn pword<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
     let bytes = buffer.as_bytes();
     let mut i : usize = 0;
 
     // first char must be alpha or '_'
-    if bytes.len() == 0 {
+    if bytes.is_empty() {
         return Err(Error::EmptyBuffer);
     } else if !is_alphabetic(bytes[0]) && bytes[i] != b'_' {
         return Err(Error::InvalidPWord);
@@ -81,49 +87,129 @@ pub fn pword<'w>(buffer: &'w str) -> Result<Split, Error> {
     }
 
     loop {
-        if i > bytes.len() {
-            return Ok(Split::new(&buffer[0..i], &buffer[i..]));
+        if i >= bytes.len() {
+            return buffer.split_at_byte(i);
         }
                 
         if !is_digit(bytes[i]) && !is_alphabetic(bytes[i]) && bytes[i] != b'_' {
-            return Ok(Split::new(&buffer[0..i], &buffer[i..]));
+            return buffer.split_at_byte(i);
         } else {
             i += 1;
         }
     }
 }
 /// Takes bytes while blank (space, tab, \n \r)
-pub fn blank<'a, 'b>(buffer: &'a str) -> Result<Split, Error> {
-    triml(buffer)
+pub fn blank<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
+    let count = buffer.as_bytes().iter().take_while(|&&b| is_blank(b as char)).count();
+    buffer.split_at_byte(count)
+}
+
+/// Same as blank but at least one blank byte is required
+pub fn blank1<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
+    let result = blank(buffer);
+
+    match result {
+        Ok(Split { left, .. }) if left.input_len() == 0 => Err(Error::ExpectedBlank),
+        _ => result,
+    }
+}
+
+/// Takes bytes while space or tab, never crosses a line
+pub fn space<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
+    let count = buffer.as_bytes().iter().take_while(|&&b| is_space(b as char)).count();
+    buffer.split_at_byte(count)
+}
+
+/// Same as space but at least one space or tab is required
+pub fn space1<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
+    let result = space(buffer);
+
+    match result {
+        Ok(Split { left, .. }) if left.input_len() == 0 => Err(Error::ExpectedBlank),
+        _ => result,
+    }
+}
+
+/// Takes a single `\n` or `\r\n`
+pub fn line_ending<'d, I: Input<'d>>(buffer: I) -> Result<Split<I>, Error> {
+    let bytes = buffer.as_bytes();
+    if bytes.starts_with(b"\r\n") {
+        take(buffer, 2)
+    } else if bytes.starts_with(b"\n") {
+        take(buffer, 1)
+    } else if bytes.is_empty() {
+        Err(Error::EmptyBuffer)
+    } else {
+        Err(Error::ExpectedLineEnding)
+    }
 }
 /// Takes bytes until the end of the buffer or until an `ending` is found
 /// Result does not include ending
-pub fn until<'a, 'b>(buffer: &'a str, ending: &[&'b str]) -> Result<Split<'a>, Error> {
+pub fn until<'d, I: Input<'d>>(buffer: I, ending: &[&str]) -> Result<Split<I>, Error> {
+    until_escaped(buffer, ending, &[])
+}
+
+/// Same as until but an `escape` found in the buffer is taken whole, so an
+/// ending inside it doesn't stop the scan. Escapes are kept as they are.
+pub fn until_escaped<'d, I: Input<'d>>(buffer: I, ending: &[&str], escapes: &[&str]) -> Result<Split<I>, Error> {
+    let bytes = buffer.as_bytes();
     let mut i = 0;
-    let mut temp = buffer;
-    loop {
-        for end in ending {
-            let temp = &buffer[i..];
-            if temp.starts_with(end) {
-                return Ok(Split::new(&buffer[0..i], &buffer[i..]));
-            }
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if let Some(escape) = escapes.iter().find(|e| !e.is_empty() && rest.starts_with(e.as_bytes())) {
+            i += escape.len();
+            continue;
         }
-        if i < temp.len() {
-            i += 1;
+        // endings and escapes are utf8, so in text they can only match
+        // where a char starts
+        if ending.iter().any(|e| rest.starts_with(e.as_bytes())) {
+            return buffer.split_at_byte(i);
+        }
+        i += 1;
+    }
+    buffer.split_at_byte(bytes.len())
+}
+
+/// Takes from `open` up to the `close` that matches it, both included.
+/// Opens and closes in between nest. InsufficientBuffer if the buffer
+/// ends first.
+pub fn balanced<'d, I: Input<'d>>(buffer: I, open: &str, close: &str) -> Result<Split<I>, Error> {
+    let bytes = buffer.as_bytes();
+    if !bytes.starts_with(open.as_bytes()) {
+        // the start of the open is still a match if more comes
+        if open.as_bytes().starts_with(bytes) {
+            return Err(Error::InsufficientBuffer);
+        }
+        return Err(Error::TagNotEqual);
+    }
+    let mut depth = 1;
+    let mut i = open.len();
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        // close first, so an open equal to its close doesn't nest
+        if rest.starts_with(close.as_bytes()) {
+            i += close.len();
+            depth -= 1;
+            if depth == 0 {
+                return buffer.split_at_byte(i);
+            }
+        } else if rest.starts_with(open.as_bytes()) {
+            i += open.len();
+            depth += 1;
         } else {
-            break;
+            i += 1;
         }
     }
-    return Ok(Split::new(&buffer[..], &buffer[i..]));
+    Err(Error::InsufficientBuffer)
 }
 
 #[inline]
 pub fn trim(buffer: &str) -> Result<&str, Error> {
     let result = triml(buffer);
 
-    if let Ok(Split { left, right }) = result {
+    if let Ok(Split { right, .. }) = result {
         let result = trimr(right);
-        if let Ok(Split { left, right }) = result {
+        if let Ok(Split { left, .. }) = result {
             return Ok(left);
         }
     }
@@ -131,11 +217,9 @@ pub fn trim(buffer: &str) -> Result<&str, Error> {
 }
 
 #[inline]
-pub fn trimr(buffer: &str) -> Result<Split, Error> {
+pub fn trimr(buffer: &str) -> Result<Split<&str>, Error> {
     let mut count_ws = 0;
-    let mut rchars = buffer.chars().rev();
-
-    while let Some(c) = rchars.next() {
+    for c in buffer.chars().rev() {
         if !is_blank(c) {
             break;
         }
@@ -146,11 +230,9 @@ pub fn trimr(buffer: &str) -> Result<Split, Error> {
 }
 
 #[inline]
-pub fn triml(buffer: &str) -> Result<Split, Error> {
+pub fn triml(buffer: &str) -> Result<Split<&str>, Error> {
     let mut count_ws = 0;
-    let mut rchars = buffer.chars();
-
-    while let Some(c) = rchars.next() {
+    for c in buffer.chars() {
         if !is_blank(c) {
             break;
         }
@@ -163,7 +245,7 @@ pub fn triml(buffer: &str) -> Result<Split, Error> {
 mod tests {
     use super::*;
 
-    const TEST_STRING: &'static str = "\t\r \nn͈̰̎u͔n͈̰̎i̙̮͚̦c͚̉o̼̩̰͗d͔̆̓ͥé\n\t   \t\r\r";
+    const TEST_STRING: &str = "\t\r \nn͈̰̎u͔n͈̰̎i̙̮͚̦c͚̉o̼̩̰͗d͔̆̓ͥé\n\t   \t\r\r";
 
     #[test]
     fn triml_test() {
@@ -180,4 +262,33 @@ mod tests {
         assert_eq!("n͈̰̎u͔n͈̰̎i̙̮͚̦c͚̉o̼̩̰͗d͔̆̓ͥé",
                    trim(TEST_STRING).unwrap());
     }
-}
\ No newline at end of file
+    #[test]
+    fn blank_family_test() {
+        assert_eq!("", blank("abc").unwrap().left);
+        assert!(blank1("abc").is_err());
+        assert_eq!(" \t\r\n", blank1(" \t\r\nabc").unwrap().left);
+        assert_eq!("\r\nabc", space(" \t\r\nabc").unwrap().right);
+        assert!(space1("\nabc").is_err());
+        assert_eq!("\r\n", line_ending("\r\nabc").unwrap().left);
+        assert_eq!("\n", line_ending("\nabc").unwrap().left);
+        assert!(line_ending("\rabc").is_err());
+    }
+    #[test]
+    fn until_test() {
+        assert_eq!("abcdefg", until("abcdefg", &["j", "w"]).unwrap().left);
+        assert_eq!("éà ", until("éà }}ü", &["}}"]).unwrap().left);
+        assert_eq!("n͈̰̎", until("n͈̰̎u͔n͈̰̎", &["u"]).unwrap().left);
+        let split = until_escaped("a \\}} b }} c", &["}}"], &["\\}}"]).unwrap();
+        assert_eq!("a \\}} b ", split.left);
+        assert_eq!("}} c", split.right);
+    }
+    #[test]
+    fn balanced_test() {
+        let split = balanced("{{ a {{ b }} {{}} }} c }}", "{{", "}}").unwrap();
+        assert_eq!("{{ a {{ b }} {{}} }}", split.left);
+        assert_eq!(" c }}", split.right);
+        assert_eq!("(é)", balanced("(é)(", "(", ")").unwrap().left);
+        assert!(balanced("{{ a {{ b }}", "{{", "}}").is_err());
+        assert!(balanced("a {{}}", "{{", "}}").is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::any::Any;
+use std::convert::TryFrom;
+use chain::StoreMode;
+use parser::ParsingDataTypes;
+use record::Record;
+
+/// Owned copy of a value produced by a chain
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Bytes(Vec<u8>),
+    I32(i32),
+    F64(f64),
+    U8(u8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    // Made by repetitions
+    List(Vec<Value>),
+    // Made by named parsers
+    Record(Record),
+}
+
+impl Value {
+    pub fn dtype(&self) -> ParsingDataTypes {
+        match *self {
+            Value::Str(_) => ParsingDataTypes::Str,
+            Value::Bytes(_) => ParsingDataTypes::Bytes,
+            Value::I32(_) => ParsingDataTypes::I32,
+            Value::F64(_) => ParsingDataTypes::F64,
+            Value::U8(_) => ParsingDataTypes::U8,
+            Value::U16(_) => ParsingDataTypes::U16,
+            Value::I16(_) => ParsingDataTypes::I16,
+            Value::U32(_) => ParsingDataTypes::U32,
+            Value::U64(_) => ParsingDataTypes::U64,
+            Value::I64(_) => ParsingDataTypes::I64,
+            Value::F32(_) => ParsingDataTypes::F32,
+            Value::List(_) => ParsingDataTypes::List,
+            Value::Record(_) => ParsingDataTypes::Record,
+        }
+    }
+}
+
+/// Types a chain can store into. Implement it for your own types to use
+/// them as store targets.
+pub trait FromParsed: Sized {
+    /// If values of `dtype` can become Self, checked by `Parser::validate`
+    fn accepts(dtype: ParsingDataTypes) -> bool;
+
+    /// None when the value doesn't fit, like 300 into a u8
+    fn from_parsed(value: Value) -> Option<Self>;
+
+    /// The store mode that adds to a value instead of replacing it
+    const EXTENDED_BY: Option<StoreMode> = None;
+
+    /// Adds `more` to the end, only called for `EXTENDED_BY`
+    fn extend_with(&mut self, _more: Self) {}
+}
+
+/// A store target as the parser sees it, implemented for every `FromParsed`
+pub trait Field {
+    fn holds(&self, mode: StoreMode, dtype: ParsingDataTypes) -> bool;
+
+    /// Done when the value is staged, so a value that doesn't fit fails the
+    /// parse instead of being dropped when the parse is done
+    fn convert(&self, value: Value) -> Option<Box<dyn Any>>;
+
+    /// Writes a value made by `convert`
+    fn write(&mut self, mode: StoreMode, value: Box<dyn Any>);
+}
+
+impl<T: FromParsed + Any> Field for T {
+    fn holds(&self, mode: StoreMode, dtype: ParsingDataTypes) -> bool {
+        accepts::<T>(mode, dtype)
+    }
+
+    fn convert(&self, value: Value) -> Option<Box<dyn Any>> {
+        match T::from_parsed(value) {
+            Some(t) => Some(Box::new(t)),
+            None => None,
+        }
+    }
+
+    fn write(&mut self, mode: StoreMode, value: Box<dyn Any>) {
+        if let Ok(t) = value.downcast::<T>() {
+            match mode {
+                StoreMode::Replace => *self = *t,
+                StoreMode::Append | StoreMode::Push => self.extend_with(*t),
+            }
+        }
+    }
+}
+
+/// If `mode` can write a value of `dtype` into a T
+pub fn accepts<T: FromParsed>(mode: StoreMode, dtype: ParsingDataTypes) -> bool {
+    match mode {
+        StoreMode::Replace => T::accepts(dtype),
+        _ => T::EXTENDED_BY == Some(mode) && T::accepts(dtype),
+    }
+}
+
+fn is_integer(dtype: ParsingDataTypes) -> bool {
+    matches!(dtype,
+             ParsingDataTypes::I32 |
+             ParsingDataTypes::U8 |
+             ParsingDataTypes::Byte |
+             ParsingDataTypes::U16 |
+             ParsingDataTypes::I16 |
+             ParsingDataTypes::U32 |
+             ParsingDataTypes::U64 |
+             ParsingDataTypes::I64)
+}
+
+// Integers take any integer that fits
+macro_rules! integers {
+    ($($t:ty),*) => {$(
+        impl FromParsed for $t {
+            fn accepts(dtype: ParsingDataTypes) -> bool {
+                is_integer(dtype)
+            }
+
+            fn from_parsed(value: Value) -> Option<$t> {
+                match value {
+                    Value::I32(i) => <$t>::try_from(i).ok(),
+                    Value::U8(u) => <$t>::try_from(u).ok(),
+                    Value::U16(u) => <$t>::try_from(u).ok(),
+                    Value::I16(i) => <$t>::try_from(i).ok(),
+                    Value::U32(u) => <$t>::try_from(u).ok(),
+                    Value::U64(u) => <$t>::try_from(u).ok(),
+                    Value::I64(i) => <$t>::try_from(i).ok(),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+integers!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl FromParsed for f64 {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        matches!(dtype, ParsingDataTypes::F64 | ParsingDataTypes::F32)
+    }
+
+    fn from_parsed(value: Value) -> Option<f64> {
+        match value {
+            Value::F64(f) => Some(f),
+            Value::F32(f) => Some(f64::from(f)),
+            _ => None,
+        }
+    }
+}
+
+impl FromParsed for f32 {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        dtype == ParsingDataTypes::F32
+    }
+
+    fn from_parsed(value: Value) -> Option<f32> {
+        match value {
+            Value::F32(f) => Some(f),
+            _ => None,
+        }
+    }
+}
+
+impl FromParsed for String {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        dtype == ParsingDataTypes::Str
+    }
+
+    fn from_parsed(value: Value) -> Option<String> {
+        match value {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    const EXTENDED_BY: Option<StoreMode> = Some(StoreMode::Append);
+
+    fn extend_with(&mut self, more: String) {
+        self.push_str(&more);
+    }
+}
+
+// A string of exactly one char, or a byte
+impl FromParsed for char {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        matches!(dtype, ParsingDataTypes::Str | ParsingDataTypes::U8 | ParsingDataTypes::Byte)
+    }
+
+    fn from_parsed(value: Value) -> Option<char> {
+        match value {
+            Value::Str(s) => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c),
+                    _ => None,
+                }
+            }
+            Value::U8(b) => Some(b as char),
+            _ => None,
+        }
+    }
+}
+
+// "true" or "false"
+impl FromParsed for bool {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        dtype == ParsingDataTypes::Str
+    }
+
+    fn from_parsed(value: Value) -> Option<bool> {
+        match value {
+            Value::Str(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+// An empty string, like blank columns once trimmed, is None
+impl<T: FromParsed> FromParsed for Option<T> {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        T::accepts(dtype)
+    }
+
+    fn from_parsed(value: Value) -> Option<Option<T>> {
+        match value {
+            Value::Str(ref s) if s.is_empty() => Some(None),
+            value => T::from_parsed(value).map(Some),
+        }
+    }
+}
+
+// A value becomes a one element Vec, raw bytes become one element per
+// byte when T doesn't take them whole and lists one per element
+impl<T: FromParsed> FromParsed for Vec<T> {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        T::accepts(dtype) ||
+        dtype == ParsingDataTypes::List ||
+        (dtype == ParsingDataTypes::Bytes && T::accepts(ParsingDataTypes::U8))
+    }
+
+    fn from_parsed(value: Value) -> Option<Vec<T>> {
+        if T::accepts(value.dtype()) {
+            return T::from_parsed(value).map(|t| vec![t]);
+        }
+        match value {
+            Value::Bytes(bytes) => bytes.into_iter().map(|b| T::from_parsed(Value::U8(b))).collect(),
+            Value::List(values) => values.into_iter().map(T::from_parsed).collect(),
+            _ => None,
+        }
+    }
+
+    const EXTENDED_BY: Option<StoreMode> = Some(StoreMode::Push);
+
+    fn extend_with(&mut self, mut more: Vec<T>) {
+        self.append(&mut more);
+    }
+}
+
+impl FromParsed for Record {
+    fn accepts(dtype: ParsingDataTypes) -> bool {
+        dtype == ParsingDataTypes::Record
+    }
+
+    fn from_parsed(value: Value) -> Option<Record> {
+        match value {
+            Value::Record(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+// Takes anything as it is
+impl FromParsed for Value {
+    fn accepts(_dtype: ParsingDataTypes) -> bool {
+        true
+    }
+
+    fn from_parsed(value: Value) -> Option<Value> {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_parsed_test() {
+        assert_eq!(Some(42_i64), i64::from_parsed(Value::I32(42)));
+        assert_eq!(None, u8::from_parsed(Value::I32(300)));
+        assert_eq!(None, u32::from_parsed(Value::I32(-1)));
+        assert_eq!(Some(Some(1.5)), Option::<f64>::from_parsed(Value::F32(1.5)));
+        assert_eq!(Some('x'), char::from_parsed(Value::Str("x".to_string())));
+        assert_eq!(None, char::from_parsed(Value::Str("xy".to_string())));
+        assert_eq!(Some(true), bool::from_parsed(Value::Str("true".to_string())));
+        assert_eq!(Some(vec![1_u8, 2]), Vec::<u8>::from_parsed(Value::Bytes(vec![1, 2])));
+        assert_eq!(Some(vec![vec![1_u8, 2]]), Vec::<Vec<u8>>::from_parsed(Value::Bytes(vec![1, 2])));
+
+        assert!(accepts::<String>(StoreMode::Append, ParsingDataTypes::Str));
+        assert!(!accepts::<String>(StoreMode::Push, ParsingDataTypes::Str));
+        assert!(accepts::<Vec<u16>>(StoreMode::Push, ParsingDataTypes::Byte));
+        assert!(!accepts::<i32>(StoreMode::Replace, ParsingDataTypes::Str));
+    }
+}
@@ -0,0 +1,386 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as Tokens};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, Ident, Lit, RangeLimits, Token};
+
+/// `#[derive(FixedWidth)]`, see `super_parser::fixed::FixedWidth`.
+///
+/// Fields take `#[col(range, conversion, flags..)]`:
+///  - range: 1-based columns, `31..=38` or `17` for a single column
+///  - conversion: `i32`, `f64`, `u8` or `byte`, none stores the text
+///  - flags: `trim`, `triml`, `trimr`, `opt` to leave the field alone when
+///    the conversion fails, `tail` to leave it alone when the line ends
+///    before its columns, `left`/`right` to align when writing,
+///    `precision = 3` for the decimals of written floats and
+///    `write = "name"` to write the field with `fn name(&Self) -> String`
+///
+/// `to_fixed` writes a byte of 0 as a blank and leaves blank tail columns
+/// off the end of the line.
+///
+/// `#[col(1..=6, eq = "ATOM", trimr)]` on the struct checks columns
+/// without storing them.
+#[proc_macro_derive(FixedWidth, attributes(col))]
+pub fn derive_fixed_width(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Conversion {
+    Str,
+    I32,
+    F64,
+    U8,
+    Byte,
+}
+
+struct Column {
+    // 0-based, end exclusive
+    start: usize,
+    end: usize,
+    // None for the struct's own columns, they are only checked
+    field: Option<Ident>,
+    conversion: Conversion,
+    trims: Vec<Ident>,
+    opt: bool,
+    tail: bool,
+    eq: Option<String>,
+    right: Option<bool>,
+    precision: Option<usize>,
+    write: Option<syn::Path>,
+    span: Span,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<Tokens> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref named) => &named.named,
+            _ => return Err(syn::Error::new(input.span(), "FixedWidth needs named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "FixedWidth can only be derived for structs")),
+    };
+
+    let mut columns = vec![];
+    for attr in cols(&input.attrs) {
+        let column = parse_col(attr, None)?;
+        if column.eq.is_none() {
+            return Err(syn::Error::new(column.span, "columns of the struct need an `eq = \"..\"` to check"));
+        }
+        columns.push(column);
+    }
+    for field in fields.iter() {
+        for attr in cols(&field.attrs) {
+            columns.push(parse_col(attr, field.ident.clone())?);
+        }
+    }
+    check_overlaps(&mut columns)?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    // Blank tail columns are cut, never the columns a line must have
+    let trim_tail = if columns.iter().any(|c| c.tail) {
+        let required = columns.iter().filter(|c| !c.tail).map(|c| c.end).max().unwrap_or(0);
+        quote! { ::super_parser::fixed::trim_tail(&mut line, #required); }
+    } else {
+        quote! {}
+    };
+    let mut parsers = vec![];
+    let mut writers = vec![];
+    let mut at = 0;
+    for column in columns.iter() {
+        let mut parser = vec![];
+        if column.start > at {
+            let gap = column.start - at;
+            parser.push(quote! { ::super_parser::parser::skip(#gap) });
+        }
+        at = column.end;
+        parser.push(column_parser(column));
+        // The gap goes with its column, a line can end before both
+        if column.tail {
+            parsers.push(quote! { ::super_parser::parser::opt(::super_parser::parser::seq(vec![#(#parser),*])) });
+        } else {
+            parsers.extend(parser);
+        }
+        writers.push(column_writer(column));
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::super_parser::fixed::FixedWidth for #name #ty_generics #where_clause {
+            fn parser() -> ::super_parser::parser::Parser<'static, 'static> {
+                ::super_parser::parser::seq(vec![#(#parsers),*])
+            }
+
+            fn to_fixed(&self) -> ::std::result::Result<::std::string::String, ::super_parser::fixed::WidthError> {
+                let mut line = ::std::string::String::new();
+                #(#writers)*
+                #trim_tail
+                Ok(line)
+            }
+        }
+    })
+}
+
+fn cols(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|a| a.path().is_ident("col"))
+}
+
+fn parse_col(attr: &Attribute, field: Option<Ident>) -> syn::Result<Column> {
+    let args = attr.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+    let mut args = args.into_iter();
+    let (start, end) = match args.next() {
+        Some(range) => parse_range(&range)?,
+        None => return Err(syn::Error::new(attr.span(), "expected a column range, like `31..=38`")),
+    };
+    let mut column = Column {
+        start,
+        end,
+        field,
+        conversion: Conversion::Str,
+        trims: vec![],
+        opt: false,
+        tail: false,
+        eq: None,
+        right: None,
+        precision: None,
+        write: None,
+        span: attr.span(),
+    };
+    for arg in args {
+        match arg {
+            Expr::Path(ref p) if p.path.get_ident().is_some() => {
+                let ident = p.path.get_ident().unwrap();
+                match ident.to_string().as_str() {
+                    "str" => column.conversion = Conversion::Str,
+                    "i32" => column.conversion = Conversion::I32,
+                    "f64" => column.conversion = Conversion::F64,
+                    "u8" => column.conversion = Conversion::U8,
+                    "byte" => column.conversion = Conversion::Byte,
+                    "trim" | "triml" | "trimr" => column.trims.push(ident.clone()),
+                    "opt" => column.opt = true,
+                    "tail" => column.tail = true,
+                    "left" => column.right = Some(false),
+                    "right" => column.right = Some(true),
+                    _ => return Err(syn::Error::new(ident.span(), "unknown column option")),
+                }
+            }
+            Expr::Assign(ref assign) => {
+                let key = match *assign.left {
+                    Expr::Path(ref p) if p.path.get_ident().is_some() => p.path.get_ident().unwrap().to_string(),
+                    _ => return Err(syn::Error::new(assign.left.span(), "expected `eq`, `precision` or `write`")),
+                };
+                let lit = match *assign.right {
+                    Expr::Lit(ref l) => &l.lit,
+                    _ => return Err(syn::Error::new(assign.right.span(), "expected a literal")),
+                };
+                match (key.as_str(), lit) {
+                    ("eq", Lit::Str(s)) => column.eq = Some(s.value()),
+                    ("precision", Lit::Int(i)) => column.precision = Some(i.base10_parse()?),
+                    ("write", Lit::Str(s)) => column.write = Some(s.parse()?),
+                    _ => {
+                        let message = "expected `eq = \"..\"`, `precision = N` or `write = \"..\"`";
+                        return Err(syn::Error::new(assign.span(), message));
+                    }
+                }
+            }
+            _ => return Err(syn::Error::new(arg.span(), "unknown column option")),
+        }
+    }
+    if column.tail && column.field.is_none() {
+        return Err(syn::Error::new(column.span, "the struct's own columns can't be `tail`"));
+    }
+    if column.eq.is_some() && column.field.is_some() {
+        return Err(syn::Error::new(column.span, "`eq` is for the struct's own columns"));
+    }
+    if column.write.is_some() && column.field.is_none() {
+        return Err(syn::Error::new(column.span, "`write` is for the columns of a field"));
+    }
+    Ok(column)
+}
+
+// 1-based inclusive columns to a 0-based half open range
+fn parse_range(expr: &Expr) -> syn::Result<(usize, usize)> {
+    let (first, last) = match *expr {
+        Expr::Range(ref range) => {
+            match (&range.start, &range.end, &range.limits) {
+                (Some(start), Some(end), &RangeLimits::Closed(_)) => (column_number(start)?, column_number(end)?),
+                _ => return Err(syn::Error::new(expr.span(), "expected an inclusive range, like `31..=38`")),
+            }
+        }
+        _ => {
+            let single = column_number(expr)?;
+            (single, single)
+        }
+    };
+    if first == 0 {
+        return Err(syn::Error::new(expr.span(), "columns start at 1"));
+    }
+    if last < first {
+        return Err(syn::Error::new(expr.span(), "the range ends before it starts"));
+    }
+    Ok((first - 1, last))
+}
+
+fn column_number(expr: &Expr) -> syn::Result<usize> {
+    match *expr {
+        Expr::Lit(syn::ExprLit { lit: Lit::Int(ref i), .. }) => i.base10_parse(),
+        _ => Err(syn::Error::new(expr.span(), "expected a column number")),
+    }
+}
+
+// Sorts by start, fails on the first column that starts inside the one
+// before or that isn't a tail after one
+fn check_overlaps(columns: &mut [Column]) -> syn::Result<()> {
+    columns.sort_by_key(|c| c.start);
+    for pair in columns.windows(2) {
+        if pair[0].tail && !pair[1].tail {
+            let message = format!("columns of {} follow the tail columns of {}, they need `tail` too",
+                                  describe(&pair[1]),
+                                  describe(&pair[0]));
+            return Err(syn::Error::new(pair[1].span, message));
+        }
+        if pair[1].start < pair[0].end {
+            let message = format!("columns {}..={} of {} overlap columns {}..={} of {}",
+                                  pair[1].start + 1,
+                                  pair[1].end,
+                                  describe(&pair[1]),
+                                  pair[0].start + 1,
+                                  pair[0].end,
+                                  describe(&pair[0]));
+            return Err(syn::Error::new(pair[1].span, message));
+        }
+    }
+    Ok(())
+}
+
+fn describe(column: &Column) -> String {
+    match column.field {
+        Some(ref f) => format!("`{}`", f),
+        None => "the struct".to_string(),
+    }
+}
+
+// take(width).chain(trims -> conversion -> store_at), the first op is a
+// free function and the rest are builder calls
+fn column_parser(column: &Column) -> Tokens {
+    let width = column.end - column.start;
+    let mut ops: Vec<Tokens> = column.trims.iter().map(|t| quote! { #t() }).collect();
+    if let Some(ref eq) = column.eq {
+        ops.push(quote! { eq_str(#eq) });
+    }
+    if let Some(ref field) = column.field {
+        let mut stored = vec![];
+        match column.conversion {
+            Conversion::Str => (),
+            Conversion::I32 => stored.push(quote! { to_i32() }),
+            Conversion::F64 => stored.push(quote! { to_f64() }),
+            Conversion::U8 => stored.push(quote! { to_u8() }),
+            Conversion::Byte => stored.push(quote! { to_byte() }),
+        }
+        stored.push(quote! { store_at(|record: &mut Self| &mut record.#field) });
+        if column.opt {
+            let inner = chain(&stored);
+            ops.push(quote! { opt(#inner) });
+        } else {
+            ops.extend(stored);
+        }
+    }
+    let chain = chain(&ops);
+    quote! { ::super_parser::parser::take(#width).chain(#chain) }
+}
+
+fn chain(ops: &[Tokens]) -> Tokens {
+    let first = &ops[0];
+    let rest = &ops[1..];
+    quote! { ::super_parser::chain::#first #(.#rest)* }
+}
+
+fn column_writer(column: &Column) -> Tokens {
+    let start = column.start;
+    let width = column.end - column.start;
+    let numeric = matches!(column.conversion, Conversion::I32 | Conversion::F64 | Conversion::U8);
+    let align = if column.right.unwrap_or(numeric) {
+        quote! { ::super_parser::fixed::Align::Right }
+    } else {
+        quote! { ::super_parser::fixed::Align::Left }
+    };
+    let (value, name) = match (&column.field, &column.eq) {
+        (Some(field), _) => {
+            let precision = match column.precision {
+                Some(p) => quote! { Some(#p) },
+                None => quote! { None },
+            };
+            let value = match (&column.write, column.conversion) {
+                (Some(write), _) => quote! { #write(self) },
+                (None, Conversion::Byte) => quote! {
+                    if self.#field == 0 { ::std::string::String::new() } else { (self.#field as char).to_string() }
+                },
+                (None, _) => quote! { ::super_parser::fixed::Column::column(&self.#field, #precision) },
+            };
+            (value, field.to_string())
+        }
+        (None, Some(eq)) => (quote! { #eq.to_string() }, "record".to_string()),
+        (None, None) => unreachable!(),
+    };
+    quote! {
+        ::super_parser::fixed::write_column(&mut line, #start, #width, &#value, #align, #name)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_test() {
+        let input: DeriveInput = syn::parse_str("
+            #[col(1..=6, eq = \"ATOM\")]
+            struct Atom {
+                #[col(7..=11, i32, trim)]
+                serial: i32,
+                #[col(31..=38, f64)]
+                x: f64,
+                #[col(35..=42, f64)]
+                y: f64,
+            }").unwrap();
+        let e = expand(&input).unwrap_err();
+        assert_eq!("columns 35..=42 of `y` overlap columns 31..=38 of `x`", e.to_string());
+
+        let input: DeriveInput = syn::parse_str("
+            #[col(1..=6, eq = \"ATOM\")]
+            struct Atom {
+                #[col(6, byte)]
+                kind: u8,
+            }").unwrap();
+        let e = expand(&input).unwrap_err();
+        assert_eq!("columns 6..=6 of `kind` overlap columns 1..=6 of the struct", e.to_string());
+
+        let input: DeriveInput = syn::parse_str("
+            struct Atom {
+                #[col(7..=11, i32, trim, tail)]
+                serial: i32,
+                #[col(12, byte)]
+                kind: u8,
+            }").unwrap();
+        let e = expand(&input).unwrap_err();
+        assert_eq!("columns of `kind` follow the tail columns of `serial`, they need `tail` too", e.to_string());
+
+        let input: DeriveInput = syn::parse_str("
+            struct Atom {
+                #[col(7..=11, i32, trim)]
+                serial: i32,
+                #[col(12, byte, tail)]
+                kind: u8,
+            }").unwrap();
+        assert!(expand(&input).is_ok());
+    }
+}